@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+// Defaults are the values used in production.  Tests use smaller ones.
+pub const K: usize = 20; // Max bucket size
+pub const A: usize = 3; // Parallel queries for node_lookup()
+pub const CHANNEL_SIZE: usize = 32; // Requests the node can queue for its service
+pub const DATAGRAM_SIZE: usize = 1024; // Largest datagram the service sends or receives
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Fits a FoundNode response carrying a single IPv6 peer, so that every peer can be sent to requesters.
+pub const MIN_DATAGRAM_SIZE: usize = 165;
+
+/// Protocol parameters and runtime limits shared by a node and its service.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    pub k: usize,
+    pub alpha: usize,
+    pub channel_size: usize,
+    pub datagram_size: usize,
+    pub request_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            k: K,
+            alpha: A,
+            channel_size: CHANNEL_SIZE,
+            datagram_size: DATAGRAM_SIZE,
+            request_timeout: REQUEST_TIMEOUT,
+        }
+    }
+}
+
+impl Config {
+    /// Sets the max bucket size, which is also the number of peers returned by lookups.  Panics if k is 0.
+    pub fn with_k(mut self, k: usize) -> Self {
+        assert!(k > 0, "k must be at least 1");
+        self.k = k;
+        self
+    }
+
+    /// Sets the number of queries a lookup keeps in flight at once.  Panics if alpha is 0.
+    pub fn with_alpha(mut self, alpha: usize) -> Self {
+        assert!(alpha > 0, "alpha must be at least 1");
+        self.alpha = alpha;
+        self
+    }
+
+    /// Sets the number of requests the node can queue for its service.  Panics if channel_size is 0.
+    pub fn with_channel_size(mut self, channel_size: usize) -> Self {
+        assert!(channel_size > 0, "channel size must be at least 1");
+        self.channel_size = channel_size;
+        self
+    }
+
+    /// Sets the largest datagram the service sends or receives.  Larger inbound datagrams are dropped,
+    /// and FoundNode responses are split across as many datagrams as needed to stay within it.  Panics if
+    /// datagram_size is less than MIN_DATAGRAM_SIZE.
+    pub fn with_datagram_size(mut self, datagram_size: usize) -> Self {
+        assert!(
+            datagram_size >= MIN_DATAGRAM_SIZE,
+            "datagram size must be at least {MIN_DATAGRAM_SIZE}"
+        );
+        self.datagram_size = datagram_size;
+        self
+    }
+
+    /// Sets how long the service waits for a response before failing a request with a timeout.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::Identifier;
+    use crate::message::{Message, MessageBody};
+    use crate::node::Peer;
+    use crate::socket;
+
+    #[test]
+    fn min_datagram_fits_found_node() {
+        let peer = Peer {
+            id: Identifier::from([u8::MAX; 32]),
+            socket_addr: socket::SocketAddr {
+                addr: "[ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff]:65535"
+                    .parse()
+                    .unwrap(),
+            },
+        };
+        let msg = Message {
+            target: peer,
+            request_id: [u8::MAX; 8],
+            body: MessageBody::FoundNode(peer.id, u8::MAX, u8::MAX, vec![peer]),
+        };
+        assert_eq!(socket::encoded(&msg).len(), MIN_DATAGRAM_SIZE);
+    }
+
+    #[test]
+    #[should_panic(expected = "k must be at least 1")]
+    fn reject_zero_k() {
+        Config::default().with_k(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "alpha must be at least 1")]
+    fn reject_zero_alpha() {
+        Config::default().with_alpha(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "datagram size must be at least")]
+    fn reject_small_datagram_size() {
+        Config::default().with_datagram_size(MIN_DATAGRAM_SIZE - 1);
+    }
+}
@@ -0,0 +1,64 @@
+use crate::store::StoreError;
+use std::{fmt, io};
+
+/// Errors returned by a node's public api.
+///
+/// Failures from the socket, the codec, the record store and remote peers all end up here, so
+/// callers only need to match on a single type.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    // The service's socket couldn't be bound.
+    Bind(io::ErrorKind),
+    // A message or the routing table file couldn't be decoded.
+    Codec(alloy_rlp::Error),
+    // The peer didn't respond before the request timed out.
+    Timeout,
+    // There are no peers in the routing table to send requests to.
+    NoPeers,
+    // The service isn't running, so requests can't reach it.
+    ChannelClosed,
+    // The peer responded with a message that doesn't answer the request.
+    ProtocolViolation,
+    // The message doesn't fit within the maximum datagram size.
+    MessageTooLarge,
+    // The record store couldn't be loaded.
+    Store(StoreError),
+    // A datagram couldn't be sent, or the routing table file couldn't be read or written.
+    Io(io::ErrorKind),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Bind(kind) => write!(f, "failed to bind socket: {kind}"),
+            Error::Codec(err) => write!(f, "malformed data: {err}"),
+            Error::Timeout => write!(f, "request timed out"),
+            Error::NoPeers => write!(f, "no peers in routing table"),
+            Error::ChannelClosed => write!(f, "service is not running"),
+            Error::ProtocolViolation => write!(f, "unexpected response from peer"),
+            Error::MessageTooLarge => write!(f, "message exceeds maximum datagram size"),
+            Error::Store(err) => write!(f, "record store error: {err:?}"),
+            Error::Io(kind) => write!(f, "i/o error: {kind}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<alloy_rlp::Error> for Error {
+    fn from(err: alloy_rlp::Error) -> Self {
+        Error::Codec(err)
+    }
+}
+
+impl From<StoreError> for Error {
+    fn from(err: StoreError) -> Self {
+        Error::Store(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err.kind())
+    }
+}
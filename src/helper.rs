@@ -1,23 +1,250 @@
 // NOTE: Silences `clippy` warning that originates from
 // the `construct_uint` macro which we do not wish
 // to address further
-#![allow(clippy::assign_op_pattern)]
+#![allow(clippy::assign_op_pattern, clippy::manual_div_ceil)]
 
 use crate::node::MAX_BUCKETS;
+use alloy_rlp::{RlpDecodableWrapper, RlpEncodableWrapper};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::BitXor;
+use std::str::FromStr;
 use uint::*;
 
-// TODO: pub type Identifier = U256;
-pub type Identifier = [u8; 32];
-
 construct_uint! {
     /// 256-bit unsigned integer (little endian).
     pub struct U256(4);
 }
 
-pub fn xor_bucket_index(x: &Identifier, y: &Identifier) -> usize {
-    let x = U256::from(x);
-    let y = U256::from(y);
-    let xor_distance = x ^ y;
+/// 256-bit id of a node or of a record's key.  Ids are encoded as 32 big endian bytes.
+#[derive(
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    RlpEncodableWrapper,
+    RlpDecodableWrapper,
+)]
+pub struct Identifier([u8; 32]);
+
+/// XOR distance between two ids.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Distance(U256);
+
+#[derive(Debug, PartialEq)]
+pub enum ParseIdentifierError {
+    InvalidLength,
+    InvalidHex,
+}
+
+impl Identifier {
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn distance(&self, other: &Identifier) -> Distance {
+        Distance(U256::from(self.0) ^ U256::from(other.0))
+    }
+
+    /// Orders a and b by their distance to self, closest first.
+    pub fn cmp_distance(&self, a: &Identifier, b: &Identifier) -> Ordering {
+        self.distance(a).cmp(&self.distance(b))
+    }
+
+    /// Number of leading bits shared with other.
+    pub fn prefix_len(&self, other: &Identifier) -> usize {
+        self.distance(other).leading_zeros()
+    }
+
+    /// A random id that falls within the given bucket of self's routing table.  Panics if bucket_index
+    /// isn't less than MAX_BUCKETS.
+    pub fn random_in_bucket(&self, bucket_index: usize) -> Identifier {
+        assert!(
+            bucket_index < MAX_BUCKETS,
+            "bucket index {bucket_index} is out of range"
+        );
+        let high_bit = U256::one() << bucket_index;
+        let low_bits = U256::from(Self::random().0) & (high_bit - 1);
+        (U256::from(self.0) ^ high_bit ^ low_bits).into()
+    }
+}
+
+impl Distance {
+    pub fn leading_zeros(&self) -> usize {
+        self.0.leading_zeros() as usize
+    }
+
+    /// Index of the distance's highest set bit.  None if the distance is zero.
+    pub fn log2(&self) -> Option<usize> {
+        (!self.0.is_zero()).then(|| self.0.bits() - 1)
+    }
+}
+
+impl BitXor for Identifier {
+    type Output = Distance;
+
+    fn bitxor(self, other: Identifier) -> Distance {
+        self.distance(&other)
+    }
+}
+
+impl From<[u8; 32]> for Identifier {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<U256> for Identifier {
+    fn from(value: U256) -> Self {
+        Self(value.into())
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Identifier({self})")
+    }
+}
+
+// Parses 64 hex characters, with or without a 0x prefix.
+impl FromStr for Identifier {
+    type Err = ParseIdentifierError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix("0x").unwrap_or(s);
+        if hex.len() != 64 {
+            return Err(ParseIdentifierError::InvalidLength);
+        }
+        // from_str_radix() would also accept a leading sign within each pair.
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseIdentifierError::InvalidHex);
+        }
+
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = hex
+                .get(2 * i..2 * i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or(ParseIdentifierError::InvalidHex)?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+/// Bucket i holds peers at a distance within [2^i, 2^(i+1)).  None if the ids are equal, as a node
+/// doesn't belong within its own table.
+pub fn xor_bucket_index(x: &Identifier, y: &Identifier) -> Option<usize> {
+    x.distance(y).log2()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn identifier() -> impl Strategy<Value = Identifier> {
+        any::<[u8; 32]>().prop_map(Identifier::from)
+    }
+
+    #[test]
+    fn distance() {
+        let x: Identifier = U256::from(0b1010).into();
+        let y: Identifier = U256::from(0b0110).into();
+        let z: Identifier = U256::from(0b1011).into();
+
+        assert_eq!(x ^ y, x.distance(&y));
+        assert_eq!(x.distance(&y), y.distance(&x));
+        assert_eq!(x.distance(&y).log2(), Some(3));
+        assert_eq!(x.distance(&x).log2(), None);
+        assert_eq!(x.prefix_len(&z), 255);
+        assert_eq!(x.cmp_distance(&z, &y), Ordering::Less);
+    }
+
+    #[test]
+    fn random_in_bucket() {
+        let id = Identifier::random();
+        for bucket_index in 0..MAX_BUCKETS {
+            let random_id = id.random_in_bucket(bucket_index);
+            assert_eq!(xor_bucket_index(&id, &random_id), Some(bucket_index));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "bucket index 256 is out of range")]
+    fn random_in_bucket_out_of_range() {
+        Identifier::random().random_in_bucket(MAX_BUCKETS);
+    }
+
+    #[test]
+    fn bucket_index_range() {
+        let id = Identifier::default();
+        let mut top_bit = [0u8; 32];
+        top_bit[0] = 0x80;
+
+        assert_eq!(xor_bucket_index(&id, &id), None);
+        assert_eq!(xor_bucket_index(&id, &U256::from(1).into()), Some(0));
+        assert_eq!(xor_bucket_index(&id, &U256::from(2).into()), Some(1));
+        assert_eq!(
+            xor_bucket_index(&id, &top_bit.into()),
+            Some(MAX_BUCKETS - 1)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn bucket_index_matches_distance(x in identifier(), y in identifier()) {
+            prop_assume!(x != y);
+            let bucket_index = xor_bucket_index(&x, &y).unwrap();
+            let distance = (x ^ y).0;
+            prop_assert!(bucket_index < MAX_BUCKETS);
+            prop_assert!(U256::one() << bucket_index <= distance);
+            prop_assert!(bucket_index == MAX_BUCKETS - 1 || distance < U256::one() << (bucket_index + 1));
+            prop_assert_eq!(xor_bucket_index(&y, &x), Some(bucket_index));
+        }
+
+        #[test]
+        fn random_in_bucket_matches_index(id in identifier(), bucket_index in 0..MAX_BUCKETS) {
+            prop_assert_eq!(xor_bucket_index(&id, &id.random_in_bucket(bucket_index)), Some(bucket_index));
+        }
+    }
+
+    #[test]
+    fn hex() {
+        let id: Identifier = U256::from(0xabcd).into();
+        let hex = id.to_string();
+        assert_eq!(hex.len(), 64);
+        assert!(hex.ends_with("abcd"));
 
-    MAX_BUCKETS - (xor_distance.leading_zeros() as usize)
+        assert_eq!(hex.parse::<Identifier>(), Ok(id));
+        assert_eq!(format!("0x{hex}").parse::<Identifier>(), Ok(id));
+        assert_eq!(
+            "abcd".parse::<Identifier>(),
+            Err(ParseIdentifierError::InvalidLength)
+        );
+        assert_eq!(
+            hex.replace('a', "g").parse::<Identifier>(),
+            Err(ParseIdentifierError::InvalidHex)
+        );
+        assert_eq!(
+            "+a".repeat(32).parse::<Identifier>(),
+            Err(ParseIdentifierError::InvalidHex)
+        );
+    }
 }
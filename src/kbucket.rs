@@ -1,21 +1,136 @@
+use crate::config::K;
+use crate::error::Error;
 use crate::helper::{xor_bucket_index, Identifier};
-use crate::node::{Peer, K, MAX_BUCKETS};
+use crate::node::{Peer, MAX_BUCKETS};
 use crate::socket::SocketAddr;
-use std::collections::HashMap;
+use crate::store::{from_unix_secs, unix_secs};
+use alloy_rlp::{Decodable, Encodable, RlpDecodable, RlpEncodable};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 
-#[derive(Clone, Debug, Default, PartialEq)]
+// Buckets without a lookup for this long are refreshed
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InsertResult {
+    Inserted,
+    // Peer was already present.  Holds the address it was known by, which is replaced by the new one.
+    Updated(SocketAddr),
+    // Bucket is full.  Holds the least-recently seen peer, which should be pinged
+    // and evicted if it doesn't respond.
+    Full(Peer),
+    // A node can't be added to its own table.
+    SelfEntry,
+}
+
+// A peer within a bucket.  Peers are marked unresponsive when a request to them times out,
+// and responsive again once they're seen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Entry {
+    pub peer: Peer,
+    pub responsive: bool,
+    pub last_seen: SystemTime,
+}
+
+// An entry as written to disk by KbucketTable::save().  Times are stored as unix seconds.
+#[derive(Debug, RlpEncodable, RlpDecodable)]
+struct SavedEntry {
+    peer: Peer,
+    last_seen: u64,
+}
+
+// Entries are ordered least-recently seen first.  Holds at most "limit" entries.
+// Replacements are candidates seen while the bucket was full, ordered least-recently seen first.  Holds
+// at most "limit" candidates.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Bucket {
-    pub map: HashMap<Identifier, SocketAddr>,
+    pub entries: Vec<Entry>,
+    pub replacements: Vec<Peer>,
     pub limit: usize,
+    // Last time a lookup was performed for an id within the bucket.
+    pub last_lookup: Instant,
 }
 
 impl Bucket {
-    fn add(&mut self, peer: Peer) -> Option<SocketAddr> {
-        if self.map.len() <= K {
-            self.map.insert(peer.id, peer.socket_addr)
+    fn new(limit: usize) -> Self {
+        Self {
+            entries: Default::default(),
+            replacements: Default::default(),
+            limit,
+            last_lookup: Instant::now(),
+        }
+    }
+
+    fn add(&mut self, peer: Peer) -> InsertResult {
+        let entry = Entry {
+            peer,
+            responsive: true,
+            last_seen: SystemTime::now(),
+        };
+
+        if let Some(index) = self.position(&peer.id) {
+            // Moves the peer to the tail of the bucket.
+            let old = self.entries.remove(index);
+            self.entries.push(entry);
+            InsertResult::Updated(old.peer.socket_addr)
+        } else if self.entries.len() < self.limit {
+            self.entries.push(entry);
+            InsertResult::Inserted
+        } else if let Some(index) = self.entries.iter().position(|entry| !entry.responsive) {
+            // Unresponsive peers are replaced without waiting on a liveness ping.
+            self.entries.remove(index);
+            self.entries.push(entry);
+            InsertResult::Inserted
         } else {
-            None
+            self.add_replacement(peer);
+            InsertResult::Full(self.entries[0].peer)
+        }
+    }
+
+    fn add_replacement(&mut self, peer: Peer) {
+        self.replacements
+            .retain(|replacement| replacement.id != peer.id);
+        if !self.replacements.is_empty() && self.replacements.len() >= self.limit {
+            self.replacements.remove(0);
         }
+        self.replacements.push(peer);
+    }
+
+    fn get(&self, id: &Identifier) -> Option<Peer> {
+        self.position(id).map(|index| self.entries[index].peer)
+    }
+
+    // Removes a peer and promotes the most recently seen replacement in its place.
+    fn evict(&mut self, id: &Identifier) -> Option<Peer> {
+        let evicted = self.position(id).map(|index| self.entries.remove(index))?;
+        if let Some(replacement) = self.replacements.pop() {
+            self.entries.push(Entry {
+                peer: replacement,
+                responsive: true,
+                last_seen: SystemTime::now(),
+            });
+        }
+        Some(evicted.peer)
+    }
+
+    // Evicts the peer if a replacement is waiting.  Otherwise the peer is kept, but will be
+    // replaced by the next peer that doesn't fit in the bucket.
+    fn mark_unresponsive(&mut self, id: &Identifier) {
+        if !self.replacements.is_empty() {
+            self.evict(id);
+        } else if let Some(index) = self.position(id) {
+            self.entries[index].responsive = false;
+        }
+    }
+
+    fn peers(&self) -> impl Iterator<Item = Peer> + '_ {
+        self.entries.iter().map(|entry| entry.peer)
+    }
+
+    fn position(&self, id: &Identifier) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.peer.id == *id)
     }
 }
 
@@ -29,115 +144,258 @@ pub struct KbucketTable {
 
 impl KbucketTable {
     pub fn new(id: Identifier) -> Self {
+        Self::with_bucket_size(id, K)
+    }
+
+    /// Creates a table whose buckets each hold up to bucket_size peers.
+    ///
+    /// Panics if bucket_size is 0, since a bucket that can't hold a single peer can't report which
+    /// peer to evict.
+    pub fn with_bucket_size(id: Identifier, bucket_size: usize) -> Self {
+        assert!(bucket_size > 0, "bucket size must be at least 1");
         Self {
             id,
-            buckets: vec![Default::default(); MAX_BUCKETS],
+            buckets: vec![Bucket::new(bucket_size); MAX_BUCKETS],
         }
     }
 
-    pub fn add(&mut self, peer: Peer) -> bool {
-        let bucket_index = xor_bucket_index(&self.id, &peer.id);
-        match self.buckets[bucket_index].add(peer).is_none() {
-            true => true,
-            false => false,
+    /// Adds a peer to its bucket, or marks it as the most recently seen peer if it's already present.
+    pub fn add(&mut self, peer: Peer) -> InsertResult {
+        match self.bucket_mut(&peer.id) {
+            Some(bucket) => bucket.add(peer),
+            None => InsertResult::SelfEntry,
         }
     }
 
     pub fn get(&self, id: &Identifier) -> Option<Peer> {
-        let bucket_index = xor_bucket_index(&self.id, id);
-        let bucket = &self.buckets[bucket_index];
+        self.bucket(id)?.get(id)
+    }
 
-        bucket.map.get(id).map(|socket_addr| Peer {
-            id: *id,
-            socket_addr: *socket_addr,
-        })
+    /// Writes every peer within the table, along with when it was last seen, to a file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let saved: Vec<SavedEntry> = self
+            .buckets
+            .iter()
+            .flat_map(|bucket| bucket.entries.iter())
+            .map(|entry| SavedEntry {
+                peer: entry.peer,
+                last_seen: unix_secs(entry.last_seen),
+            })
+            .collect();
+        let mut out = Vec::new();
+        saved.encode(&mut out);
+
+        // Written to a temporary file first so that a crash can't leave a partially written table.
+        let tmp_path = path.with_extension("tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&out)?;
+        tmp.sync_all()?;
+        fs::rename(tmp_path, path)
     }
 
-    pub fn get_closest_nodes(&self, id: &Identifier, x: usize) -> Option<Vec<Peer>> {
-        // Diff in cursors keep the index from repeating in first iteration of function
-        let mut l_cursor: i32 = 1;
-        let mut r_cursor: i32 = 0;
-
-        //  Utilize left and right cursors.  Think of this as left and right of a number line:
-        //      0, 1, 2, ... target, ... 254, 255
-        let mut closest_peers: Vec<Peer> = Vec::new();
-        let target_index = xor_bucket_index(&self.id, id) as i32;
-        let mut current_index;
-
-        for _ in 0..256 {
-            if target_index + r_cursor < 256 {
-                current_index = target_index + r_cursor;
-                if let Some(peers) = self.bucket_peers(current_index) {
-                    for peer in peers {
-                        if closest_peers.len() < x {
-                            closest_peers.push(peer);
-                        } else {
-                            return Some(closest_peers);
-                        }
-                    }
-                }
-                r_cursor += 1;
+    /// Adds the peers saved to a file by save().  Peers keep their last seen times, and each bucket keeps
+    /// its least-recently seen first order.  Returns the number of peers added.  Fails with Error::Codec if
+    /// the file is corrupt.
+    pub fn load(&mut self, path: &Path) -> Result<usize, Error> {
+        let data = fs::read(path)?;
+        let saved = Vec::<SavedEntry>::decode(&mut data.as_slice())?;
+        // Last seen times too far in the future to represent mean the file is corrupt.
+        let saved: Vec<(Peer, SystemTime)> = saved
+            .into_iter()
+            .map(|SavedEntry { peer, last_seen }| Some((peer, from_unix_secs(last_seen)?)))
+            .collect::<Option<_>>()
+            .ok_or(Error::Codec(alloy_rlp::Error::Custom(
+                "timestamp out of range",
+            )))?;
+
+        let mut added = 0;
+        for (peer, last_seen) in saved {
+            if self.add(peer) != InsertResult::Inserted {
+                continue;
             }
-            if target_index - l_cursor >= 0 {
-                current_index = target_index - l_cursor;
-                if let Some(peers) = self.bucket_peers(current_index) {
-                    for peer in peers {
-                        if closest_peers.len() < x {
-                            closest_peers.push(peer);
-                        } else {
-                            return Some(closest_peers);
-                        }
-                    }
+            if let Some(bucket) = self.bucket_mut(&peer.id) {
+                if let Some(index) = bucket.position(&peer.id) {
+                    bucket.entries[index].last_seen = last_seen;
+                    added += 1;
                 }
-                l_cursor += 1;
             }
         }
+        Ok(added)
+    }
+
+    /// Removes an unresponsive peer from the table.  The most recently seen replacement
+    /// for its bucket takes its place.
+    pub fn evict(&mut self, id: &Identifier) -> Option<Peer> {
+        self.bucket_mut(id)?.evict(id)
+    }
+
+    /// Records that a request to the peer went unanswered.
+    pub fn mark_unresponsive(&mut self, id: &Identifier) {
+        if let Some(bucket) = self.bucket_mut(id) {
+            bucket.mark_unresponsive(id);
+        }
+    }
+
+    /// Records a lookup for an id, which keeps its bucket from needing a refresh.
+    pub fn mark_lookup(&mut self, id: &Identifier) {
+        if let Some(bucket) = self.bucket_mut(id) {
+            bucket.last_lookup = Instant::now();
+        }
+    }
+
+    /// Indices of buckets that haven't had a lookup within REFRESH_INTERVAL of now.
+    pub fn idle_buckets(&self, now: Instant) -> Vec<usize> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, bucket)| now.duration_since(bucket.last_lookup) >= REFRESH_INTERVAL)
+            .map(|(bucket_index, _)| bucket_index)
+            .collect()
+    }
+
+    /// The x closest peers to an id, sorted by XOR distance to it.
+    pub fn get_closest_nodes(&self, id: &Identifier, x: usize) -> Option<Vec<Peer>> {
+        let mut closest_peers: Vec<Peer> = self
+            .buckets
+            .iter()
+            .flat_map(|bucket| bucket.peers())
+            .collect();
         if closest_peers.is_empty() {
             return None;
         }
+
+        closest_peers.sort_by(|a, b| id.cmp_distance(&a.id, &b.id));
+        closest_peers.truncate(x);
         Some(closest_peers)
     }
 
-    fn bucket_peers(&self, i: i32) -> Option<Vec<Peer>> {
-        let bucket = &self.buckets[i as usize];
-        let mut bucket_peers = Vec::new();
+    /// Number of peers within the table that are closer to `id` than the local node.
+    pub fn closer_peers(&self, id: &Identifier) -> usize {
+        let local_distance = self.id.distance(id);
 
-        // Cycle through bucket.
-        for (k, v) in bucket.map.iter() {
-            let peer = Peer {
-                id: *k,
-                socket_addr: *v,
-            };
-            bucket_peers.push(peer);
-        }
-        if bucket_peers.is_empty() {
-            return None;
-        }
-        Some(bucket_peers)
+        self.buckets
+            .iter()
+            .flat_map(|bucket| bucket.peers())
+            .filter(|peer| peer.id.distance(id) < local_distance)
+            .count()
+    }
+
+    // Bucket an id falls within.  None for the table's own id.
+    fn bucket(&self, id: &Identifier) -> Option<&Bucket> {
+        xor_bucket_index(&self.id, id).map(|bucket_index| &self.buckets[bucket_index])
+    }
+
+    fn bucket_mut(&mut self, id: &Identifier) -> Option<&mut Bucket> {
+        xor_bucket_index(&self.id, id).map(|bucket_index| &mut self.buckets[bucket_index])
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::config::Config;
     use crate::helper::U256;
     use crate::node::Node;
     use crate::socket;
+    use proptest::prelude::*;
     use std::net::{IpAddr, SocketAddr};
+    use std::time::UNIX_EPOCH;
+
+    // Small enough for a bucket's entries and replacements to share bucket 4, which holds ids 16..32.
+    const BUCKET_SIZE: usize = 7;
+
+    fn peer(i: u64) -> Peer {
+        Peer {
+            id: U256::from(i).into(),
+            socket_addr: socket::SocketAddr {
+                addr: SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6000 + i as u16),
+            },
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "bucket size must be at least 1")]
+    fn reject_empty_bucket_size() {
+        KbucketTable::with_bucket_size(U256::from(0).into(), 0);
+    }
+
+    #[test]
+    fn least_recently_seen_eviction() {
+        let mut table = KbucketTable::with_bucket_size(U256::from(0).into(), BUCKET_SIZE);
+
+        // Bucket 4 holds ids 16..32.
+        for i in 16..16 + BUCKET_SIZE as u64 {
+            assert_eq!(table.add(peer(i)), InsertResult::Inserted);
+        }
+        assert_eq!(table.add(peer(30)), InsertResult::Full(peer(16)));
+
+        // Seeing the oldest peer again moves it to the tail of the bucket.
+        assert_eq!(
+            table.add(peer(16)),
+            InsertResult::Updated(peer(16).socket_addr)
+        );
+        assert_eq!(table.add(peer(30)), InsertResult::Full(peer(17)));
+
+        assert_eq!(table.evict(&peer(17).id), Some(peer(17)));
+        assert_eq!(table.buckets[4].entries.last().unwrap().peer, peer(30));
+    }
+
+    #[test]
+    fn promote_replacement() {
+        let mut table = KbucketTable::with_bucket_size(U256::from(0).into(), BUCKET_SIZE);
+        for i in 16..16 + BUCKET_SIZE as u64 {
+            table.add(peer(i));
+        }
+
+        // Candidates seen while the bucket is full are cached, up to BUCKET_SIZE.
+        for i in 24..24 + BUCKET_SIZE as u64 + 1 {
+            assert_eq!(table.add(peer(i)), InsertResult::Full(peer(16)));
+        }
+        assert_eq!(table.buckets[4].replacements.len(), BUCKET_SIZE);
+        assert!(!table.buckets[4].replacements.contains(&peer(24)));
+
+        // The most recently seen replacement is promoted.
+        let newest = peer(24 + BUCKET_SIZE as u64);
+        assert_eq!(table.evict(&peer(16).id), Some(peer(16)));
+        assert_eq!(table.get(&newest.id), Some(newest));
+        assert!(!table.buckets[4].replacements.contains(&newest));
+    }
+
+    #[test]
+    fn replace_unresponsive_peer() {
+        let mut table = KbucketTable::with_bucket_size(U256::from(0).into(), BUCKET_SIZE);
+        for i in 16..16 + BUCKET_SIZE as u64 {
+            table.add(peer(i));
+        }
+
+        // Without a replacement waiting, unresponsive peers stay until a new peer needs the space.
+        table.mark_unresponsive(&peer(20).id);
+        assert!(!table.buckets[4].entries[4].responsive);
+        assert_eq!(table.add(peer(30)), InsertResult::Inserted);
+        assert_eq!(table.get(&peer(20).id), None);
+
+        // With a replacement waiting, unresponsive peers are evicted immediately.
+        assert_eq!(table.add(peer(31)), InsertResult::Full(peer(16)));
+        table.mark_unresponsive(&peer(17).id);
+        assert_eq!(table.get(&peer(17).id), None);
+        assert_eq!(table.get(&peer(31).id), Some(peer(31)));
+    }
 
     #[test]
     fn get_closest_nodes() {
         let local = Node::new(
             U256::from(0).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6000),
+            Config::default(),
         );
         let node_to_find = Node::new(
             U256::from(13).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6013),
+            Config::default(),
         );
 
         // Populate local node's table
-        let mut table = KbucketTable::new(local.id);
+        let mut table = KbucketTable::with_bucket_size(local.id, BUCKET_SIZE);
         for i in 2..30 {
             if i == 13 {
                 continue;
@@ -155,12 +413,9 @@ mod test {
             table.add(peer);
         }
 
-        // Creates our expected response
+        // Creates our expected response, ordered by distance to node_to_find
         let mut expected_peers: Vec<Peer> = Vec::new();
-        for i in 8..16 {
-            if i == 13 {
-                continue;
-            }
+        for i in [12, 15, 14, 9, 8, 11, 10] {
             let port = "600".to_string() + &i.to_string();
             let peer = Peer {
                 id: U256::from(i).into(),
@@ -174,9 +429,123 @@ mod test {
             expected_peers.push(peer);
         }
 
-        let mut closest_nodes = table.get_closest_nodes(&node_to_find.id, K).unwrap();
-        closest_nodes.sort_by(|a, b| a.id.partial_cmp(&b.id).unwrap());
+        let closest_nodes = table
+            .get_closest_nodes(&node_to_find.id, BUCKET_SIZE)
+            .unwrap();
 
         assert_eq!(closest_nodes, expected_peers);
     }
+
+    #[test]
+    fn idle_buckets() {
+        let local: Identifier = U256::from(0).into();
+        let mut table = KbucketTable::new(local);
+        assert!(table.idle_buckets(Instant::now()).is_empty());
+
+        let later = Instant::now() + REFRESH_INTERVAL;
+        table.buckets[4].last_lookup = later;
+        assert_eq!(table.idle_buckets(later).len(), MAX_BUCKETS - 1);
+        assert!(!table.idle_buckets(later).contains(&4));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("table-{}.rlp", rand::random::<u64>()));
+        let mut table = KbucketTable::with_bucket_size(U256::from(0).into(), BUCKET_SIZE);
+        for i in 16..20 {
+            table.add(peer(i));
+        }
+        table.buckets[4].entries[0].last_seen = UNIX_EPOCH + Duration::from_secs(1_000);
+        table.save(&path).unwrap();
+
+        let mut reloaded = KbucketTable::new(table.id);
+        assert_eq!(reloaded.load(&path).unwrap(), 4);
+        let _ = fs::remove_file(&path);
+
+        let peers: Vec<Peer> = reloaded.buckets[4].peers().collect();
+        assert_eq!(peers, vec![peer(16), peer(17), peer(18), peer(19)]);
+        for (entry, reloaded_entry) in table.buckets[4]
+            .entries
+            .iter()
+            .zip(&reloaded.buckets[4].entries)
+        {
+            assert_eq!(
+                unix_secs(entry.last_seen),
+                unix_secs(reloaded_entry.last_seen)
+            );
+        }
+    }
+
+    #[test]
+    fn load_out_of_range_last_seen() {
+        let path = std::env::temp_dir().join(format!("table-{}.rlp", rand::random::<u64>()));
+        let saved = vec![SavedEntry {
+            peer: peer(16),
+            last_seen: u64::MAX,
+        }];
+        let mut out = Vec::new();
+        saved.encode(&mut out);
+        fs::write(&path, out).unwrap();
+
+        let mut table = KbucketTable::with_bucket_size(U256::from(0).into(), BUCKET_SIZE);
+        let result = table.load(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(
+            result,
+            Err(Error::Codec(alloy_rlp::Error::Custom(
+                "timestamp out of range"
+            )))
+        );
+        assert_eq!(table.get(&peer(16).id), None);
+    }
+
+    #[test]
+    fn bucket_size() {
+        let mut table = KbucketTable::with_bucket_size(U256::from(0).into(), 2);
+        assert_eq!(table.add(peer(16)), InsertResult::Inserted);
+        assert_eq!(table.add(peer(17)), InsertResult::Inserted);
+        assert_eq!(table.add(peer(18)), InsertResult::Full(peer(16)));
+        assert_eq!(table.buckets[4].entries.len(), 2);
+    }
+
+    #[test]
+    fn update_address() {
+        let mut table = KbucketTable::with_bucket_size(U256::from(0).into(), BUCKET_SIZE);
+        table.add(peer(16));
+
+        // The peer is now reachable through a different port.
+        let moved = Peer {
+            socket_addr: peer(17).socket_addr,
+            ..peer(16)
+        };
+        assert_eq!(
+            table.add(moved),
+            InsertResult::Updated(peer(16).socket_addr)
+        );
+        assert_eq!(table.get(&moved.id), Some(moved));
+    }
+
+    #[test]
+    fn reject_local_id() {
+        let mut table = KbucketTable::with_bucket_size(U256::from(0).into(), BUCKET_SIZE);
+        assert_eq!(table.add(peer(0)), InsertResult::SelfEntry);
+        assert_eq!(table.get(&peer(0).id), None);
+        assert_eq!(table.get_closest_nodes(&peer(0).id, BUCKET_SIZE), None);
+    }
+
+    proptest! {
+        #[test]
+        fn add_any_id(local in any::<[u8; 32]>(), ids in proptest::collection::vec(any::<[u8; 32]>(), 1..64)) {
+            let mut table = KbucketTable::with_bucket_size(local.into(), BUCKET_SIZE);
+            for id in ids {
+                let peer = Peer { id: id.into(), ..peer(1) };
+                match table.add(peer) {
+                    InsertResult::SelfEntry => prop_assert_eq!(peer.id, table.id),
+                    InsertResult::Full(_) => prop_assert_eq!(table.get(&peer.id), None),
+                    _ => prop_assert_eq!(table.get(&peer.id), Some(peer)),
+                }
+            }
+            prop_assert!(table.buckets.iter().all(|bucket| bucket.entries.len() <= BUCKET_SIZE));
+        }
+    }
 }
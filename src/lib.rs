@@ -1,9 +1,12 @@
+pub mod config;
+pub mod error;
 pub mod helper;
 pub mod kbucket;
 pub mod message;
 pub mod node;
 pub mod service;
 pub mod socket;
+pub mod store;
 
 // Expose for our Kademlia client RPCs here:
 // TODO:
@@ -11,12 +14,6 @@ pub mod socket;
 /*
 fn ping() {}
 
-// TODO:
-fn store() {}
-
 // TODO:
 fn find_node() {}
-
-// TODO:
-fn find_value() {}
  */
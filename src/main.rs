@@ -1,9 +1,12 @@
+pub mod config;
+pub mod error;
 pub mod helper;
 pub mod kbucket;
 pub mod message;
 pub mod node;
 pub mod service;
 pub mod socket;
+pub mod store;
 
 fn main() {
     // Bootstrapping protocol -
@@ -12,7 +15,7 @@ fn main() {
     // a node lookup for its own node ID.  Finally, u refreshes all k-buckets further away
     // than its closest neighbor."
     //
-    // TODO:  Implement bootstrapping
+    // See Node::bootstrap().
     //
     println!("Let's build this thing");
 }
@@ -1,40 +1,116 @@
 use crate::helper::Identifier;
 use crate::node::Peer;
 use alloy_rlp::{encode_list, Decodable, Encodable, Error, RlpDecodable, RlpEncodable};
+use bytes::Bytes;
 use tokio::sync::oneshot;
-type TotalNodes = u8;
+// FoundNode responses may be split across datagrams.  Each part carries the number of parts and its index.
+type TotalParts = u8;
+type PartIndex = u8;
+// Random id echoed by every response, matching it to its request.
+pub type RequestId = [u8; 8];
+type Ttl = u64; // Seconds until a stored record expires
 
-#[derive(Debug)]
-pub enum DecoderError {
-    Malformed,
-}
+// Channel used by the service to resolve a request sent on behalf of the node.
+pub type Responder<T> = oneshot::Sender<Result<T, crate::error::Error>>;
 
 #[derive(Debug, RlpEncodable, RlpDecodable)]
 pub struct Message {
     pub target: Peer,
-    pub session: u8,
+    pub request_id: RequestId,
     pub body: MessageBody,
 }
 
+/// Response to a FindValue request: either the value itself or the responder's closest peers.
+#[derive(Debug)]
+pub enum ValueResponse {
+    // The value, along with its original publisher.
+    Value(Vec<u8>, Identifier),
+    Peers(Vec<Peer>),
+}
+
 // TODO: Impl PartialEq for MessageBody so we can verify serialization
 //       and deserialization within tests
 #[derive(Debug)]
 pub enum MessageBody {
-    Ping(Identifier, Option<oneshot::Sender<bool>>), // 0
-    Pong(Identifier),                                // 1
-    FindNode(
+    Ping(Identifier, Option<Responder<bool>>), // 0
+    Pong(Identifier),                          // 1
+    FindNode(Identifier, Identifier, Option<Responder<Option<Vec<Peer>>>>), // 2
+    FoundNode(Identifier, TotalParts, PartIndex, Vec<Peer>), // 3
+    Store(
+        Identifier,
         Identifier,
+        Vec<u8>,
+        Ttl,
+        Identifier, // Original publisher
+        Option<Responder<bool>>,
+    ), // 4
+    Stored(Identifier),                        // 5
+    FindValue(
         Identifier,
-        Option<oneshot::Sender<Option<Vec<Peer>>>>,
-    ), // 2
-    FoundNode(Identifier, TotalNodes, Vec<Peer>),    // 3
+        Identifier,
+        Option<Responder<Option<ValueResponse>>>,
+    ), // 6
+    FoundValue(Identifier, Vec<u8>, Identifier), // 7
 }
 
-//  +----------+---------+---------+----------+
-//  | msg type | session | node_id |   body   |
-//  +----------+---------+---------+----------+
-//  |  1 byte  |  1 byte | 32 bytes|    'n'   |
-//  +----------+---------+---------+----------+
+impl MessageBody {
+    pub fn is_request(&self) -> bool {
+        matches!(
+            self,
+            Self::Ping(..) | Self::FindNode(..) | Self::Store(..) | Self::FindValue(..)
+        )
+    }
+
+    /// Id of the peer that sent the message.
+    pub fn sender(&self) -> Identifier {
+        match self {
+            Self::Ping(id, _)
+            | Self::Pong(id)
+            | Self::FindNode(id, _, _)
+            | Self::FoundNode(id, _, _, _)
+            | Self::Store(id, _, _, _, _, _)
+            | Self::Stored(id)
+            | Self::FindValue(id, _, _)
+            | Self::FoundValue(id, _, _) => *id,
+        }
+    }
+
+    pub fn is_response_to(&self, request: &MessageBody) -> bool {
+        matches!(
+            (self, request),
+            (Self::Pong(..), Self::Ping(..))
+                | (Self::FoundNode(..), Self::FindNode(..))
+                | (Self::FoundNode(..), Self::FindValue(..))
+                | (Self::Stored(..), Self::Store(..))
+                | (Self::FoundValue(..), Self::FindValue(..))
+        )
+    }
+
+    /// Resolves a request's response channel with an error.
+    pub fn fail(self, err: crate::error::Error) {
+        match self {
+            Self::Ping(_, Some(tx)) => {
+                let _ = tx.send(Err(err));
+            }
+            Self::FindNode(_, _, Some(tx)) => {
+                let _ = tx.send(Err(err));
+            }
+            Self::Store(_, _, _, _, _, Some(tx)) => {
+                let _ = tx.send(Err(err));
+            }
+            Self::FindValue(_, _, Some(tx)) => {
+                let _ = tx.send(Err(err));
+            }
+            _ => {}
+        }
+    }
+}
+
+//  +----------+------------+---------+----------+
+//  | msg type | request id | node_id |   body   |
+//  +----------+------------+---------+----------+
+//  |  1 byte  |   8 bytes  | 32 bytes|    'n'   |
+//  +----------+------------+---------+----------+
 impl Encodable for MessageBody {
     fn encode(&self, out: &mut dyn bytes::BufMut) {
         match self {
@@ -57,12 +133,46 @@ impl Encodable for MessageBody {
                 enc[2] = &node_to_find;
                 encode_list::<_, dyn Encodable>(&enc, out);
             }
-            Self::FoundNode(req_id, total_nodes, closest_nodes) => {
-                let mut enc: [&dyn Encodable; 4] = [b""; 4];
+            Self::FoundNode(req_id, total, index, closest_nodes) => {
+                let mut enc: [&dyn Encodable; 5] = [b""; 5];
                 enc[0] = &3_u8;
                 enc[1] = &req_id;
-                enc[2] = &total_nodes;
-                enc[3] = closest_nodes;
+                enc[2] = &total;
+                enc[3] = &index;
+                enc[4] = closest_nodes;
+                encode_list::<_, dyn Encodable>(&enc, out);
+            }
+            Self::Store(req_id, key, value, ttl, publisher, _) => {
+                let value: &[u8] = value;
+                let mut enc: [&dyn Encodable; 6] = [b""; 6];
+                enc[0] = &4_u8;
+                enc[1] = &req_id;
+                enc[2] = &key;
+                enc[3] = &value;
+                enc[4] = &ttl;
+                enc[5] = &publisher;
+                encode_list::<_, dyn Encodable>(&enc, out);
+            }
+            Self::Stored(id) => {
+                let mut enc: [&dyn Encodable; 2] = [b""; 2];
+                enc[0] = &5_u8;
+                enc[1] = &id;
+                encode_list::<_, dyn Encodable>(&enc, out);
+            }
+            Self::FindValue(req_id, key, _) => {
+                let mut enc: [&dyn Encodable; 3] = [b""; 3];
+                enc[0] = &6_u8;
+                enc[1] = &req_id;
+                enc[2] = &key;
+                encode_list::<_, dyn Encodable>(&enc, out);
+            }
+            Self::FoundValue(req_id, value, publisher) => {
+                let value: &[u8] = value;
+                let mut enc: [&dyn Encodable; 4] = [b""; 4];
+                enc[0] = &7_u8;
+                enc[1] = &req_id;
+                enc[2] = &value;
+                enc[3] = &publisher;
                 encode_list::<_, dyn Encodable>(&enc, out);
             }
         }
@@ -76,25 +186,49 @@ impl Decodable for MessageBody {
         let typ = u8::decode(&mut payload)?;
         let msg = match typ {
             0 => {
-                let id = <[u8; 32]>::decode(&mut payload)?;
+                let id = Identifier::decode(&mut payload)?;
                 MessageBody::Ping(id, None)
             }
             1 => {
-                let id = <[u8; 32]>::decode(&mut payload)?;
+                let id = Identifier::decode(&mut payload)?;
                 MessageBody::Pong(id)
             }
             2 => {
-                let id = <[u8; 32]>::decode(&mut payload)?;
-                let target = <[u8; 32]>::decode(&mut payload)?;
+                let id = Identifier::decode(&mut payload)?;
+                let target = Identifier::decode(&mut payload)?;
                 MessageBody::FindNode(id, target, None)
             }
             3 => {
-                let id = <[u8; 32]>::decode(&mut payload)?;
+                let id = Identifier::decode(&mut payload)?;
                 let total = <u8>::decode(&mut payload)?;
+                let index = <u8>::decode(&mut payload)?;
                 let peers = <Vec<Peer>>::decode(&mut payload)?;
-                MessageBody::FoundNode(id, total, peers)
+                MessageBody::FoundNode(id, total, index, peers)
+            }
+            4 => {
+                let id = Identifier::decode(&mut payload)?;
+                let key = Identifier::decode(&mut payload)?;
+                let value = Bytes::decode(&mut payload)?;
+                let ttl = <u64>::decode(&mut payload)?;
+                let publisher = Identifier::decode(&mut payload)?;
+                MessageBody::Store(id, key, value.to_vec(), ttl, publisher, None)
+            }
+            5 => {
+                let id = Identifier::decode(&mut payload)?;
+                MessageBody::Stored(id)
+            }
+            6 => {
+                let id = Identifier::decode(&mut payload)?;
+                let key = Identifier::decode(&mut payload)?;
+                MessageBody::FindValue(id, key, None)
             }
-            _ => panic!(),
+            7 => {
+                let id = Identifier::decode(&mut payload)?;
+                let value = Bytes::decode(&mut payload)?;
+                let publisher = Identifier::decode(&mut payload)?;
+                MessageBody::FoundValue(id, value.to_vec(), publisher)
+            }
+            _ => return Err(Error::Custom("unknown message type")),
         };
         Ok(msg)
     }
@@ -106,12 +240,13 @@ mod test {
     use crate::helper::U256;
     use crate::socket;
     use bytes::BytesMut;
+    use proptest::prelude::*;
     use std::net::{IpAddr, SocketAddr};
     // One shot channels don't allow us to #[derive(PartialEq)] on msg body.
     // Print statements instead.
     #[test]
     fn serialize_ping() {
-        let id = [0u8; 32];
+        let id = Identifier::from([0u8; 32]);
         let body = MessageBody::Ping(id, None);
         println!("Body: {:?}", body);
 
@@ -125,7 +260,7 @@ mod test {
 
     #[test]
     fn serialize_pong() {
-        let id = [0u8; 32];
+        let id = Identifier::from([0u8; 32]);
         let body = MessageBody::Pong(id);
         println!("Body: {:?}", body);
 
@@ -139,8 +274,8 @@ mod test {
 
     #[test]
     fn serialize_find_node() {
-        let id = [0u8; 32];
-        let target = [1u8; 32];
+        let id = Identifier::from([0u8; 32]);
+        let target = Identifier::from([1u8; 32]);
         let body = MessageBody::FindNode(id, target, None);
         println!("Body: {:?}", body);
 
@@ -154,9 +289,10 @@ mod test {
 
     #[test]
     fn serialize_found_node() {
-        let local_id = [0u8; 32];
+        let local_id = Identifier::from([0u8; 32]);
 
         let total = 2;
+        let index = 1;
         let mut closest_peers = Vec::new();
         let peer1 = Peer {
             id: U256::from(1).into(),
@@ -173,7 +309,7 @@ mod test {
         };
         closest_peers.push(peer2);
 
-        let body = MessageBody::FoundNode(local_id, total, closest_peers);
+        let body = MessageBody::FoundNode(local_id, total, index, closest_peers);
         println!("Body: {:?}", body);
 
         let mut out = BytesMut::new();
@@ -183,4 +319,186 @@ mod test {
         assert!(result.is_ok());
         println!("\n");
     }
+
+    #[test]
+    fn serialize_store() {
+        let id = Identifier::from([0u8; 32]);
+        let key = Identifier::from([1u8; 32]);
+        let value = b"sample".to_vec();
+        let ttl = 3600;
+        let publisher = Identifier::from([2u8; 32]);
+        let body = MessageBody::Store(id, key, value.clone(), ttl, publisher, None);
+        println!("Body: {:?}", body);
+
+        let mut out = BytesMut::new();
+        body.encode(&mut out);
+        let result = MessageBody::decode(&mut out.to_vec().as_slice());
+        println!("Result: {:?}", result);
+        if let Ok(MessageBody::Store(
+            _,
+            decoded_key,
+            decoded_value,
+            decoded_ttl,
+            decoded_publisher,
+            None,
+        )) = result
+        {
+            assert_eq!(decoded_key, key);
+            assert_eq!(decoded_value, value);
+            assert_eq!(decoded_ttl, ttl);
+            assert_eq!(decoded_publisher, publisher);
+        } else {
+            panic!()
+        }
+        println!("\n");
+    }
+
+    #[test]
+    fn serialize_stored() {
+        let id = Identifier::from([0u8; 32]);
+        let body = MessageBody::Stored(id);
+        println!("Body: {:?}", body);
+
+        let mut out = BytesMut::new();
+        body.encode(&mut out);
+        let result = MessageBody::decode(&mut out.to_vec().as_slice());
+        println!("Result: {:?}", result);
+        assert!(result.is_ok());
+        println!("\n");
+    }
+
+    #[test]
+    fn serialize_find_value() {
+        let id = Identifier::from([0u8; 32]);
+        let key = Identifier::from([1u8; 32]);
+        let body = MessageBody::FindValue(id, key, None);
+        println!("Body: {:?}", body);
+
+        let mut out = BytesMut::new();
+        body.encode(&mut out);
+        let result = MessageBody::decode(&mut out.to_vec().as_slice());
+        println!("Result: {:?}", result);
+        assert!(result.is_ok());
+        println!("\n");
+    }
+
+    #[test]
+    fn serialize_found_value() {
+        let id = Identifier::from([0u8; 32]);
+        let value = b"sample".to_vec();
+        let body = MessageBody::FoundValue(id, value.clone(), id);
+        println!("Body: {:?}", body);
+
+        let mut out = BytesMut::new();
+        body.encode(&mut out);
+        let result = MessageBody::decode(&mut out.to_vec().as_slice());
+        println!("Result: {:?}", result);
+        if let Ok(MessageBody::FoundValue(_, decoded_value, _)) = result {
+            assert_eq!(decoded_value, value);
+        } else {
+            panic!()
+        }
+        println!("\n");
+    }
+
+    #[test]
+    fn reject_unknown_type() {
+        let id = Identifier::from([0u8; 32]);
+        let mut enc: [&dyn Encodable; 2] = [b""; 2];
+        enc[0] = &8_u8;
+        enc[1] = &id;
+        let mut out = BytesMut::new();
+        encode_list::<_, dyn Encodable>(&enc, &mut out);
+
+        let result = MessageBody::decode(&mut out.to_vec().as_slice());
+        assert_eq!(result.err(), Some(Error::Custom("unknown message type")));
+    }
+
+    fn identifier() -> impl Strategy<Value = Identifier> {
+        any::<[u8; 32]>().prop_map(Identifier::from)
+    }
+
+    fn peer() -> impl Strategy<Value = Peer> {
+        (identifier(), any::<SocketAddr>()).prop_map(|(id, addr)| Peer {
+            id,
+            socket_addr: socket::SocketAddr { addr },
+        })
+    }
+
+    fn value() -> impl Strategy<Value = Vec<u8>> {
+        proptest::collection::vec(any::<u8>(), 0..64)
+    }
+
+    fn body() -> impl Strategy<Value = MessageBody> {
+        prop_oneof![
+            identifier().prop_map(|id| MessageBody::Ping(id, None)),
+            identifier().prop_map(MessageBody::Pong),
+            (identifier(), identifier())
+                .prop_map(|(id, target)| MessageBody::FindNode(id, target, None)),
+            (
+                identifier(),
+                any::<u8>(),
+                any::<u8>(),
+                proptest::collection::vec(peer(), 0..8)
+            )
+                .prop_map(|(id, total, index, peers)| MessageBody::FoundNode(
+                    id, total, index, peers
+                )),
+            (
+                identifier(),
+                identifier(),
+                value(),
+                any::<u64>(),
+                identifier()
+            )
+                .prop_map(|(id, key, value, ttl, publisher)| MessageBody::Store(
+                    id, key, value, ttl, publisher, None
+                )),
+            identifier().prop_map(MessageBody::Stored),
+            (identifier(), identifier())
+                .prop_map(|(id, key)| MessageBody::FindValue(id, key, None)),
+            (identifier(), value(), identifier())
+                .prop_map(|(id, value, publisher)| MessageBody::FoundValue(id, value, publisher)),
+        ]
+    }
+
+    fn message() -> impl Strategy<Value = Message> {
+        (peer(), any::<RequestId>(), body()).prop_map(|(target, request_id, body)| Message {
+            target,
+            request_id,
+            body,
+        })
+    }
+
+    // Decoding must never panic, as datagrams come from untrusted peers.
+    proptest! {
+        #[test]
+        fn decode_arbitrary_bytes(data in proptest::collection::vec(any::<u8>(), 0..256)) {
+            let _ = Message::decode(&mut data.as_slice());
+            let _ = MessageBody::decode(&mut data.as_slice());
+        }
+
+        #[test]
+        fn decode_encoded(msg in message()) {
+            let out = socket::encoded(&msg);
+            let decoded = Message::decode(&mut out.to_vec().as_slice());
+            prop_assert!(decoded.is_ok());
+            prop_assert_eq!(socket::encoded(&decoded.unwrap()), out);
+        }
+
+        #[test]
+        fn decode_corrupted(msg in message(), index in any::<prop::sample::Index>(), byte in any::<u8>()) {
+            let mut data = socket::encoded(&msg).to_vec();
+            let i = index.index(data.len());
+            data[i] = byte;
+            let _ = Message::decode(&mut data.as_slice());
+        }
+
+        #[test]
+        fn decode_truncated(msg in message(), len in any::<prop::sample::Index>()) {
+            let mut data = socket::encoded(&msg).to_vec();
+            data.truncate(len.index(data.len()));
+            prop_assert!(Message::decode(&mut data.as_slice()).is_err());
+        }
+    }
 }
@@ -1,22 +1,27 @@
-use crate::helper::Identifier;
+use crate::config::Config;
+use crate::error::Error;
+use crate::helper::{xor_bucket_index, Identifier};
 use crate::kbucket::KbucketTable;
-use crate::message::{Message, MessageBody};
-use crate::service::Service;
+use crate::message::{Message, MessageBody, ValueResponse};
+use crate::service::{Service, Stats};
 use crate::socket::{self, SocketAddr};
+use crate::store::{
+    MemoryStore, Record, RecordStore, CACHE_TTL, REPLICATE_INTERVAL, REPUBLISH_INTERVAL,
+};
 use alloy_rlp::{RlpDecodable, RlpEncodable};
-use rand::Rng;
 use std::{
-    collections::HashMap,
-    net,
+    collections::HashSet,
+    io, net,
+    path::PathBuf,
     sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
 };
 use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
 use tokio::time::Duration;
 
-//  Typically 20.  Only 7 for testing
-pub const K: usize = 7; // Max bucket size
-const A: usize = 3; // Parallel queries for node_lookup()
 pub const MAX_BUCKETS: usize = 256;
+const REFRESH_CHECK_INTERVAL: Duration = Duration::from_secs(60); // How often idle buckets are looked for
 
 #[derive(Clone, Copy, Debug, PartialEq, RlpEncodable, RlpDecodable)]
 pub struct Peer {
@@ -24,67 +29,276 @@ pub struct Peer {
     pub socket_addr: socket::SocketAddr,
 }
 
+// Outcome of an iterative lookup.
+enum LookupOutcome {
+    // The K closest peers that responded.
+    Closest(Vec<Peer>),
+    // The value and its original publisher, along with the closest peer that responded without it.
+    Value(Vec<u8>, Identifier, Option<Peer>),
+}
+
 // The main Kademlia client struct.
 // Provides user-level API for performing querie and interacting with the underlying service.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Node {
     pub id: Identifier,
     pub socket: SocketAddr,
     pub service_tx: Option<mpsc::Sender<Message>>,
     pub table: Arc<Mutex<KbucketTable>>,
-    pub outbound_requests: HashMap<Identifier, Message>,
+    pub store: Arc<Mutex<dyn RecordStore>>,
+    pub config: Config,
+    pub table_file: Option<PathBuf>,
+    pub stats: Arc<Stats>,
 }
 
 impl Node {
-    pub fn new(id: Identifier, socket: net::SocketAddr) -> Self {
+    pub fn new(id: Identifier, socket: net::SocketAddr, config: Config) -> Self {
         Self {
             id,
             socket: SocketAddr { addr: socket },
             service_tx: None,
-            table: Arc::new(Mutex::new(KbucketTable::new(id))),
-            outbound_requests: (Default::default()),
+            table: Arc::new(Mutex::new(KbucketTable::with_bucket_size(id, config.k))),
+            store: Arc::new(Mutex::new(MemoryStore::default())),
+            config,
+            table_file: None,
+            stats: Default::default(),
         }
     }
 
+    /// Swaps the default in-memory record store for a custom implementation.
+    pub fn with_store(mut self, store: impl RecordStore + 'static) -> Self {
+        self.store = Arc::new(Mutex::new(store));
+        self
+    }
+
+    /// Saves the routing table to a file, so that a restarted node can rejoin through its saved peers.
+    /// The table is loaded when the node starts and saved every REFRESH_CHECK_INTERVAL.
+    pub fn with_table_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.table_file = Some(path.into());
+        self
+    }
+
+    /// Counts of rejected responses and dropped datagrams since the node started.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
     // Protocol's Exposed functions:
     // ---------------------------------------------------------------------------------------------------
-    /// The node_lookup function iteratively calls our find_node rpc to query the "a" closest nodes to an id.
-    /// With each response, our local node updates its routing table and calls the next closest peers etc...
+    /// The node_lookup function iteratively calls our find_node rpc to find the K closest peers to an id.
+    ///
+    /// A shortlist of peers is kept sorted by distance to the id, with up to "alpha" queries in flight at once.
+    /// Peers learned from each response are merged into the shortlist and peers that fail to respond are
+    /// dropped from it.  The lookup converges once the K closest peers in the shortlist have all responded.
+    ///
+    /// Fails with Error::NoPeers if the routing table is empty.
     ///
     /// Note: Routing table is updated within service when response is received.
-    pub async fn node_lookup(&mut self, id: Identifier) -> Vec<Peer> {
-        let mut query_depth = 0;
-
-        while query_depth < 5 {
-            // 1. Grab "A" closest peers from table.
-            let targets = {
-                let table = &self.table.lock().unwrap();
-                if let Some(targets) = table.get_closest_nodes(&id, A) {
-                    targets
+    pub async fn node_lookup(&mut self, id: Identifier) -> Result<Vec<Peer>, Error> {
+        match self.lookup(id, false).await? {
+            LookupOutcome::Closest(peers) => Ok(peers),
+            LookupOutcome::Value(..) => unreachable!("node lookups don't request values"),
+        }
+    }
+
+    /// Joins the network through the given seed peers.
+    ///
+    /// Seeds are inserted into the routing table, then a node lookup is performed for our own id.  Finally
+    /// every bucket farther away than our closest neighbour is refreshed with a lookup for an id within it.
+    /// Returns the number of peers learned, or Error::NoPeers if no seed responded.
+    pub async fn bootstrap(&mut self, seeds: Vec<Peer>) -> Result<usize, Error> {
+        let known_peers = self.responsive_peers();
+        {
+            let mut table = self.table.lock().unwrap();
+            for seed in seeds {
+                table.add(seed);
+            }
+        }
+        if self.responsive_peers() == 0 {
+            return Err(Error::NoPeers);
+        }
+
+        let closest_peers = self.node_lookup(self.id).await?;
+        let Some(closest_neighbour) = closest_peers.first() else {
+            return Err(Error::NoPeers);
+        };
+
+        let farther_buckets = xor_bucket_index(&self.id, &closest_neighbour.id)
+            .map_or(0, |bucket_index| bucket_index + 1)..MAX_BUCKETS;
+        self.refresh(farther_buckets.collect()).await;
+
+        Ok(self.responsive_peers().saturating_sub(known_peers))
+    }
+
+    /// Rejoins the network through the peers restored from the table file, without needing seed nodes.
+    ///
+    /// Every peer within the routing table is pinged.  Those that don't respond are marked unresponsive, so
+    /// they're replaced as new peers are seen.  A node lookup is then performed for our own id.
+    /// Returns the number of peers that responded, or Error::NoPeers if none did.
+    pub async fn rejoin(&mut self) -> Result<usize, Error> {
+        let peers: Vec<Peer> = {
+            let table = self.table.lock().unwrap();
+            table
+                .buckets
+                .iter()
+                .flat_map(|bucket| bucket.entries.iter().map(|entry| entry.peer))
+                .collect()
+        };
+
+        let mut pings = JoinSet::new();
+        for peer in peers {
+            let mut node = self.clone();
+            pings.spawn(async move { node.ping(peer.id).await });
+        }
+        let mut responded = 0;
+        while let Some(result) = pings.join_next().await {
+            if let Ok(Ok(true)) = result {
+                responded += 1;
+            }
+        }
+        if responded == 0 {
+            return Err(Error::NoPeers);
+        }
+
+        self.node_lookup(self.id).await?;
+        Ok(responded)
+    }
+
+    /// Writes the routing table to the table file set by with_table_file().  Does nothing if no table file
+    /// is set.
+    pub fn save_table(&self) -> Result<(), Error> {
+        let Some(path) = &self.table_file else {
+            return Ok(());
+        };
+        Ok(self.table.lock().unwrap().save(path)?)
+    }
+
+    /// Refreshes buckets that haven't had a lookup within REFRESH_INTERVAL with a lookup for a random id
+    /// within each.  Returns the number of buckets refreshed.
+    pub async fn refresh_buckets(&mut self) -> Result<usize, Error> {
+        Ok(self.refresh_idle_buckets(Instant::now()).await)
+    }
+
+    async fn refresh_idle_buckets(&mut self, now: Instant) -> usize {
+        let idle_buckets = self.table.lock().unwrap().idle_buckets(now);
+        if idle_buckets.is_empty() || self.responsive_peers() == 0 {
+            return 0;
+        }
+
+        let refreshed = idle_buckets.len();
+        self.refresh(idle_buckets).await;
+        refreshed
+    }
+
+    // Refreshes run concurrently, as each can wait on unresponsive peers.
+    async fn refresh(&mut self, bucket_indices: Vec<usize>) {
+        let mut refreshes = JoinSet::new();
+        for bucket_index in bucket_indices {
+            let mut node = self.clone();
+            let target = self.id.random_in_bucket(bucket_index);
+            refreshes.spawn(async move {
+                let _ = node.node_lookup(target).await;
+            });
+        }
+        while refreshes.join_next().await.is_some() {}
+    }
+
+    // Iterative lookup shared by node_lookup() and get_value().  Value lookups terminate as soon as any
+    // peer returns the value.
+    async fn lookup(&mut self, id: Identifier, find_value: bool) -> Result<LookupOutcome, Error> {
+        let mut shortlist = {
+            let table = &mut self.table.lock().unwrap();
+            table.mark_lookup(&id);
+            table
+                .get_closest_nodes(&id, self.config.k)
+                .ok_or(Error::NoPeers)?
+        };
+
+        let mut queried = HashSet::new();
+        let mut responded = HashSet::new();
+        let mut in_flight = JoinSet::new();
+
+        loop {
+            // 1. Keep "alpha" queries in flight to the closest unqueried peers.
+            let targets: Vec<Peer> = shortlist
+                .iter()
+                .take(self.config.k)
+                .filter(|peer| !queried.contains(&peer.id))
+                .take(self.config.alpha.saturating_sub(in_flight.len()))
+                .copied()
+                .collect();
+            for peer in targets {
+                queried.insert(peer.id);
+                if find_value {
+                    let rx = self.find_value_targeted(id, peer).await?;
+                    in_flight.spawn(async move {
+                        let response = rx.await.ok().and_then(|r| r.ok()).flatten();
+                        (peer, response)
+                    });
                 } else {
-                    panic!("No nodes in table");
+                    let rx = self.find_node_targeted(id, peer).await?;
+                    in_flight.spawn(async move {
+                        let peers = rx.await.ok().and_then(|r| r.ok()).flatten();
+                        (peer, peers.map(ValueResponse::Peers))
+                    });
                 }
+            }
+
+            // 2. Process the next response.  Nothing in flight means there's no one left to query.
+            let Some(Ok((peer, response))) = in_flight.join_next().await else {
+                break;
             };
+            match response {
+                Some(ValueResponse::Value(value, publisher)) => {
+                    // Closest peer that responded without the value.
+                    let closest_miss = shortlist
+                        .iter()
+                        .find(|peer| responded.contains(&peer.id))
+                        .copied();
+                    return Ok(LookupOutcome::Value(value, publisher, closest_miss));
+                }
+                Some(ValueResponse::Peers(peers)) => {
+                    responded.insert(peer.id);
+                    for peer in peers {
+                        let is_new = shortlist.iter().all(|known| known.id != peer.id);
+                        if is_new && peer.id != self.id {
+                            shortlist.push(peer);
+                        }
+                    }
+                    shortlist.sort_by(|a, b| id.cmp_distance(&a.id, &b.id));
+                }
+                None => shortlist.retain(|known| known.id != peer.id),
+            }
 
-            // 2. Send find_node request to each peer.
-            for peer in targets {
-                self.find_node_targeted(id, peer).await;
+            // 3. Converged once the K closest peers have all responded.
+            if shortlist
+                .iter()
+                .take(self.config.k)
+                .all(|peer| responded.contains(&peer.id))
+            {
+                break;
             }
-            // Delay is here to give a reasonable amount of time for responses to come in.
-            tokio::time::sleep(Duration::from_secs(4)).await;
+        }
 
-            // Printing to verify table has been updated (remove once testing functionality is implemented)
-            let table = &self.table.lock().unwrap();
-            println!("Table (After round 1 Node Lookup): {:?}", table);
+        shortlist.retain(|peer| responded.contains(&peer.id));
+        shortlist.truncate(self.config.k);
+        Ok(LookupOutcome::Closest(shortlist))
+    }
 
-            query_depth += 1;
-        }
+    fn responsive_peers(&self) -> usize {
+        let table = self.table.lock().unwrap();
+        table
+            .buckets
+            .iter()
+            .flat_map(|bucket| bucket.entries.iter())
+            .filter(|entry| entry.responsive)
+            .count()
+    }
 
-        if let Some(peers) = &self.table.lock().unwrap().get_closest_nodes(&id, K) {
-            peers.to_vec()
-        } else {
-            panic!("No nodes in table")
-        }
+    // Hands a request to the service.  Fails if the node hasn't been started or its service has stopped.
+    async fn send(&self, msg: Message) -> Result<(), Error> {
+        let service_tx = self.service_tx.as_ref().ok_or(Error::ChannelClosed)?;
+        service_tx.send(msg).await.map_err(|_| Error::ChannelClosed)
     }
 
     // Modified find_node rpc leveraged within node_lookup()
@@ -92,55 +306,56 @@ impl Node {
         &mut self,
         id: Identifier,
         target: Peer,
-    ) -> oneshot::Receiver<Option<Vec<Peer>>> {
+    ) -> Result<oneshot::Receiver<Result<Option<Vec<Peer>>, Error>>, Error> {
         let (tx, rx) = oneshot::channel();
         let msg = Message {
             target,
-            session: (rand::thread_rng().gen_range(0..=255)),
+            request_id: rand::random(),
             body: (MessageBody::FindNode(self.id, id, Some(tx))),
         };
 
-        let _ = self.service_tx.as_ref().unwrap().send(msg).await;
-        rx
+        self.send(msg).await?;
+        Ok(rx)
     }
 
     /// Note: This function is async because the service processes inbound reqs from rpcs one at a time.  
     /// service_tx.send() doesn't require a response to happen immediately!  Access rx response by assigning fn a
     /// variable.
-    pub async fn find_node(&mut self, id: Identifier) -> Option<Vec<Peer>> {
+    ///
+    /// Fails with Error::NoPeers if the routing table is empty.
+    pub async fn find_node(&mut self, id: Identifier) -> Result<Option<Vec<Peer>>, Error> {
         let target = {
             let table = &self.table.lock().unwrap();
             let target = table.get(&id);
 
             if target.is_some() {
                 println!("Node is already in table!");
-                return None;
-            }
-            if let Some(target) = table.get_closest_nodes(&id, K) {
-                target[0]
-            } else {
-                println!("No nodes in routing table");
-                return None;
+                return Ok(None);
             }
+            let Some(closest) = table.get_closest_nodes(&id, self.config.k) else {
+                return Err(Error::NoPeers);
+            };
+            closest[0]
         };
 
         let (tx, rx) = oneshot::channel();
         let msg = Message {
             target,
-            session: (rand::thread_rng().gen_range(0..=255)),
+            request_id: rand::random(),
             body: (MessageBody::FindNode(self.id, id, Some(tx))),
         };
 
-        let _ = self.service_tx.as_ref().unwrap().send(msg).await;
-        rx.await.unwrap()
+        self.send(msg).await?;
+        rx.await.map_err(|_| Error::ChannelClosed)?
     }
 
-    pub async fn ping(&mut self, id: Identifier) -> bool {
+    /// Fails with Error::Timeout if the peer doesn't respond in time.
+    pub async fn ping(&mut self, id: Identifier) -> Result<bool, Error> {
         let peer = {
             let table = &self.table.lock().unwrap();
             let target = table.get(&id);
             if target.is_none() {
-                return false;
+                return Ok(false);
             }
             target.unwrap()
         };
@@ -149,27 +364,223 @@ impl Node {
 
         let msg = Message {
             target: peer,
-            session: (rand::thread_rng().gen_range(0..=255)),
+            request_id: rand::random(),
             body: (MessageBody::Ping(self.id, Some(tx))),
         };
 
-        let _ = self.service_tx.as_ref().unwrap().send(msg).await;
-        rx.await.unwrap()
+        self.send(msg).await?;
+        rx.await.map_err(|_| Error::ChannelClosed)?
+    }
+
+    /// Stores a value on the K closest peers to the key found by node_lookup().
+    /// The local node keeps a copy as the record's publisher and republishes it every REPUBLISH_INTERVAL.
+    /// Returns the number of peers that acknowledged the store.
+    pub async fn store(&mut self, key: Identifier, value: Vec<u8>) -> Result<usize, Error> {
+        let record = Record::new(key, value, self.id);
+        self.store.lock().unwrap().put(record.clone())?;
+
+        self.replicate(&record).await
+    }
+
+    /// Republishes records we're the original publisher of every REPUBLISH_INTERVAL and replicates
+    /// records we hold every REPLICATE_INTERVAL.  Held records that were stored to us within the last
+    /// REPLICATE_INTERVAL are skipped, as the peer who sent it has just replicated it.
+    pub async fn republish(&mut self) -> Result<(), Error> {
+        let now = SystemTime::now();
+        let due: Vec<Record> = self
+            .store
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|record| !record.is_expired(now))
+            .filter(|record| {
+                let interval = if record.publisher == self.id {
+                    REPUBLISH_INTERVAL
+                } else {
+                    REPLICATE_INTERVAL
+                };
+                now.duration_since(record.received_at).unwrap_or_default() >= interval
+            })
+            .collect();
+
+        for record in due {
+            if record.publisher == self.id {
+                // Republishing restarts the record's expiration.
+                let republished = Record::new(record.key, record.value, self.id);
+                self.store.lock().unwrap().put(republished.clone())?;
+                self.replicate(&republished).await?;
+            } else {
+                self.replicate(&record).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Sends a store request for a record to the K closest peers to its key.  Returns the number of peers that
+    // acknowledged it.
+    async fn replicate(&mut self, record: &Record) -> Result<usize, Error> {
+        let peers = self.node_lookup(record.key).await?;
+        let ttl = record
+            .expires
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+
+        let mut receivers = Vec::new();
+        for peer in peers.into_iter().filter(|peer| peer.id != self.id) {
+            let (tx, rx) = oneshot::channel();
+            let msg = Message {
+                target: peer,
+                request_id: rand::random(),
+                body: (MessageBody::Store(
+                    self.id,
+                    record.key,
+                    record.value.clone(),
+                    ttl.as_secs(),
+                    record.publisher,
+                    Some(tx),
+                )),
+            };
+
+            self.send(msg).await?;
+            receivers.push(rx);
+        }
+
+        // Peers that don't acknowledge the store before their request times out are not counted.
+        let mut stored = 0;
+        for rx in receivers {
+            if let Ok(Ok(true)) = rx.await {
+                stored += 1;
+            }
+        }
+        Ok(stored)
+    }
+
+    /// The get_value function performs an iterative lookup for a key using our find_value rpc.  Peers that
+    /// don't hold the value respond with their closest peers, as they would for find_node.  The lookup terminates
+    /// as soon as any peer returns the value, which is then cached at the closest peer that didn't return it.
+    pub async fn get_value(&mut self, key: Identifier) -> Result<Option<Vec<u8>>, Error> {
+        let record = self
+            .store
+            .lock()
+            .unwrap()
+            .get(&key)
+            .filter(|record| !record.is_expired(SystemTime::now()));
+        if let Some(record) = record {
+            return Ok(Some(record.value));
+        }
+
+        match self.lookup(key, true).await? {
+            LookupOutcome::Value(value, publisher, closest_miss) => {
+                if let Some(peer) = closest_miss {
+                    self.cache(key, value.clone(), publisher, peer).await?;
+                }
+                Ok(Some(value))
+            }
+            LookupOutcome::Closest(_) => Ok(None),
+        }
+    }
+
+    // Caches a value found by get_value() at a peer along the lookup path.  Cached copies expire
+    // sooner than records stored by their publisher.
+    async fn cache(
+        &mut self,
+        key: Identifier,
+        value: Vec<u8>,
+        publisher: Identifier,
+        target: Peer,
+    ) -> Result<(), Error> {
+        // The acknowledgement isn't awaited.
+        let (tx, _) = oneshot::channel();
+        let msg = Message {
+            target,
+            request_id: rand::random(),
+            body: (MessageBody::Store(
+                self.id,
+                key,
+                value,
+                CACHE_TTL.as_secs(),
+                publisher,
+                Some(tx),
+            )),
+        };
+
+        self.send(msg).await
+    }
+
+    // Modified find_value rpc leveraged within get_value()
+    pub async fn find_value_targeted(
+        &mut self,
+        key: Identifier,
+        target: Peer,
+    ) -> Result<oneshot::Receiver<Result<Option<ValueResponse>, Error>>, Error> {
+        let (tx, rx) = oneshot::channel();
+        let msg = Message {
+            target,
+            request_id: rand::random(),
+            body: (MessageBody::FindValue(self.id, key, Some(tx))),
+        };
+
+        self.send(msg).await?;
+        Ok(rx)
     }
 
     // ---------------------------------------------------------------------------------------------------
 
-    pub async fn start(&mut self) -> Result<(), &'static str> {
+    pub async fn start(&mut self) -> Result<(), Error> {
+        self.store.lock().unwrap().load()?;
+        if let Some(path) = &self.table_file {
+            match self.table.lock().unwrap().load(path) {
+                Err(Error::Io(io::ErrorKind::NotFound)) | Ok(_) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
         let local_record = Peer {
             id: self.id,
             socket_addr: self.socket,
         };
-        if let Some(service_tx) = Service::spawn(local_record, self.table.clone()).await {
-            self.service_tx = Some(service_tx);
-            Ok(())
-        } else {
-            Err("Service wasn't created")
-        }
+        let service_tx = Service::spawn(
+            local_record,
+            self.table.clone(),
+            self.store.clone(),
+            self.stats.clone(),
+            self.config,
+        )
+        .await?;
+        self.service_tx = Some(service_tx);
+        self.spawn_republisher();
+        self.spawn_refresher();
+        Ok(())
+    }
+
+    // Background task that checks for idle buckets every REFRESH_CHECK_INTERVAL.  The routing table is
+    // saved afterwards if a table file is set.
+    fn spawn_refresher(&self) {
+        let mut node = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REFRESH_CHECK_INTERVAL);
+            // The first tick completes immediately.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let _ = node.refresh_buckets().await;
+                let _ = node.save_table();
+            }
+        });
+    }
+
+    // Background task that checks for records due to be republished every REPLICATE_INTERVAL.
+    fn spawn_republisher(&self) {
+        let mut node = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REPLICATE_INTERVAL);
+            // The first tick completes immediately.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let _ = node.republish().await;
+            }
+        });
     }
 }
 
@@ -181,17 +592,28 @@ impl Node {
 mod tests {
     use super::*;
     use crate::helper::U256;
+    use crate::kbucket::REFRESH_INTERVAL;
+    use crate::store::{Record, StoreError, MAX_VALUE_BYTES};
     use std::net::{IpAddr, SocketAddr};
 
+    // Small enough for a single bucket to fill up with test peers.
+    const K: usize = 7;
+
+    fn config() -> Config {
+        Config::default().with_k(K)
+    }
+
     #[tokio::test]
     async fn ping_rpc() {
         let mut local = Node::new(
             U256::from(0).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6000),
+            config(),
         );
         let mut remote = Node::new(
             U256::from(1).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6001),
+            config(),
         );
         local.table.lock().unwrap().add(Peer {
             id: remote.id,
@@ -203,15 +625,16 @@ mod tests {
 
         tokio::time::sleep(Duration::from_secs(1)).await;
         let ping = local.ping(remote.id);
-        assert!(ping.await);
+        assert_eq!(ping.await, Ok(true));
         tokio::time::sleep(Duration::from_secs(1)).await;
 
         let dummy = Node::new(
             U256::from(2).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6002),
+            config(),
         );
         let ping = local.ping(dummy.id);
-        assert!(!ping.await);
+        assert_eq!(ping.await, Ok(false));
     }
 
     #[allow(warnings)]
@@ -220,14 +643,17 @@ mod tests {
         let mut local = Node::new(
             U256::from(0).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6000),
+            config(),
         );
         let mut remote = Node::new(
             U256::from(1).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6001),
+            config(),
         );
         let node_to_find = Node::new(
             U256::from(13).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6003),
+            config(),
         );
 
         local.table.lock().unwrap().add(Peer {
@@ -289,7 +715,7 @@ mod tests {
         tokio::time::sleep(Duration::from_secs(1)).await;
         let find_node = local.find_node(node_to_find.id);
 
-        if let Some(mut closest_nodes) = find_node.await {
+        if let Ok(Some(mut closest_nodes)) = find_node.await {
             closest_nodes.sort_by(|a, b| a.id.partial_cmp(&b.id).unwrap());
             assert_eq!(closest_nodes, expected_peers);
         } else {
@@ -302,10 +728,12 @@ mod tests {
         let mut local = Node::new(
             U256::from(0).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6000),
+            config(),
         );
         let mut remote = Node::new(
             U256::from(1).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6001),
+            config(),
         );
         let remote_peer = Peer {
             id: remote.id,
@@ -315,6 +743,7 @@ mod tests {
         let node_to_find = Node::new(
             U256::from(13).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6003),
+            config(),
         );
 
         local.table.lock().unwrap().add(Peer {
@@ -366,10 +795,13 @@ mod tests {
         let _ = remote.start().await;
         tokio::time::sleep(Duration::from_secs(1)).await;
 
-        let rx = local.find_node_targeted(node_to_find.id, remote_peer).await;
+        let rx = local
+            .find_node_targeted(node_to_find.id, remote_peer)
+            .await
+            .unwrap();
         tokio::time::sleep(Duration::from_secs(1)).await;
 
-        if let Some(mut closest_nodes) = rx.await.unwrap() {
+        if let Ok(Some(mut closest_nodes)) = rx.await.unwrap() {
             closest_nodes.sort_by(|a, b| a.id.partial_cmp(&b.id).unwrap());
             assert_eq!(closest_nodes, expected_peers);
         } else {
@@ -384,10 +816,12 @@ mod tests {
         let mut local = Node::new(
             U256::from(0).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6000),
+            config(),
         );
         let node_to_find = Node::new(
             U256::from(3).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6003),
+            config(),
         );
 
         // Here we create nodes to add to local's routing table.
@@ -395,21 +829,25 @@ mod tests {
         let mut remote1 = Node::new(
             U256::from(1).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6001),
+            config(),
         );
         remote_nodes.push(&remote1);
         let mut remote5 = Node::new(
             U256::from(5).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6005),
+            config(),
         );
         remote_nodes.push(&remote5);
         let mut remote7 = Node::new(
             U256::from(7).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6007),
+            config(),
         );
         remote_nodes.push(&remote7);
         let mut remote20 = Node::new(
             U256::from(20).into(),
             SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6020),
+            config(),
         );
         remote_nodes.push(&remote20);
 
@@ -439,6 +877,7 @@ mod tests {
                             ),
                         },
                     };
+                    // Ignored for the remote's own id.
                     remote_table.add(peer);
                 }
             }
@@ -455,7 +894,806 @@ mod tests {
 
         println!("Table (Pre Node Lookup): {:?}", local.table);
         println!("\n");
-        local.node_lookup(node_to_find.id).await;
+        let closest_peers = local.node_lookup(node_to_find.id).await.unwrap();
+
+        // Only our running remotes respond.  Results are sorted by distance to the node we're looking for.
+        let closest_ids: Vec<Identifier> = closest_peers.iter().map(|peer| peer.id).collect();
+        let expected_ids: Vec<Identifier> = [1, 7, 5, 20]
+            .into_iter()
+            .map(|i| U256::from(i).into())
+            .collect();
+        assert_eq!(closest_ids, expected_ids);
+    }
+
+    #[tokio::test]
+    async fn store_rpc() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6100),
+            config(),
+        );
+        let mut remote1 = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6101),
+            config(),
+        );
+        let mut remote2 = Node::new(
+            U256::from(2).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6102),
+            config(),
+        );
+        let remote1_peer = Peer {
+            id: remote1.id,
+            socket_addr: remote1.socket,
+        };
+        let remote2_peer = Peer {
+            id: remote2.id,
+            socket_addr: remote2.socket,
+        };
+
+        // Populate tables.  Remotes only know about each other.
+        {
+            let mut local_table = local.table.lock().unwrap();
+            local_table.add(remote1_peer);
+            local_table.add(remote2_peer);
+        }
+        remote1.table.lock().unwrap().add(remote2_peer);
+        remote2.table.lock().unwrap().add(remote1_peer);
+
+        let _ = local.start().await;
+        let _ = remote1.start().await;
+        let _ = remote2.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let key: Identifier = U256::from(3).into();
+        let value = b"sample".to_vec();
+        let stored = local.store(key, value.clone()).await;
+
+        assert_eq!(stored, Ok(2));
+        let stored_value = |node: &Node| node.store.lock().unwrap().get(&key).map(|r| r.value);
+        assert_eq!(stored_value(&remote1), Some(value.clone()));
+        assert_eq!(stored_value(&remote2), Some(value));
+    }
+
+    #[tokio::test]
+    async fn get_value() {
+        // Local only knows remote1, who knows remote2, who holds the value.
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6200),
+            config(),
+        );
+        let mut remote1 = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6201),
+            config(),
+        );
+        let mut remote2 = Node::new(
+            U256::from(2).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6202),
+            config(),
+        );
+        let remote1_peer = Peer {
+            id: remote1.id,
+            socket_addr: remote1.socket,
+        };
+        let remote2_peer = Peer {
+            id: remote2.id,
+            socket_addr: remote2.socket,
+        };
+
+        local.table.lock().unwrap().add(remote1_peer);
+        remote1.table.lock().unwrap().add(remote2_peer);
+        remote2.table.lock().unwrap().add(remote1_peer);
+
+        let key: Identifier = U256::from(3).into();
+        let value = b"sample".to_vec();
+        let record = Record::new(key, value.clone(), remote2.id);
+        let _ = remote2.store.lock().unwrap().put(record);
+
+        let _ = local.start().await;
+        let _ = remote1.start().await;
+        let _ = remote2.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        assert_eq!(local.get_value(key).await, Ok(Some(value.clone())));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // Remote1 was queried but didn't hold the value, so it should now hold a cached copy.
+        let cached = remote1.store.lock().unwrap().get(&key).unwrap();
+        assert_eq!(cached.value, value);
+        assert!(cached.expires <= SystemTime::now() + CACHE_TTL);
+
+        let missing_key: Identifier = U256::from(4).into();
+        assert_eq!(local.get_value(missing_key).await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn skip_expired_local_record() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7215),
+            config(),
+        );
+        let key: Identifier = U256::from(3).into();
+        let record = Record::new(key, b"sample".to_vec(), local.id).with_ttl(Duration::ZERO);
+        let _ = local.store.lock().unwrap().put(record);
+
+        // The expired record isn't served, so the value is looked up through peers.
+        assert_eq!(local.get_value(key).await, Err(Error::NoPeers));
+    }
+
+    #[tokio::test]
+    async fn republish() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6300),
+            config(),
+        );
+        let mut remote1 = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6301),
+            config(),
+        );
+        let mut remote2 = Node::new(
+            U256::from(2).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6302),
+            config(),
+        );
+        let remote1_peer = Peer {
+            id: remote1.id,
+            socket_addr: remote1.socket,
+        };
+        let remote2_peer = Peer {
+            id: remote2.id,
+            socket_addr: remote2.socket,
+        };
+
+        local.table.lock().unwrap().add(remote1_peer);
+        remote1.table.lock().unwrap().add(remote2_peer);
+        remote2.table.lock().unwrap().add(remote1_peer);
+
+        // Local received the record from remote2 over an hour ago, so it's due for replication.
+        let key: Identifier = U256::from(3).into();
+        let value = b"sample".to_vec();
+        let mut record = Record::new(key, value.clone(), remote2.id);
+        record.received_at -= REPLICATE_INTERVAL;
+        let _ = local.store.lock().unwrap().put(record);
+
+        let _ = local.start().await;
+        let _ = remote1.start().await;
+        let _ = remote2.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        local.republish().await.unwrap();
+
+        let stored_value = |node: &Node| node.store.lock().unwrap().get(&key).map(|r| r.value);
+        assert_eq!(stored_value(&remote1), Some(value.clone()));
+        assert_eq!(stored_value(&remote2), Some(value));
+
+        // Replicated records keep their original expiration.
+        let expires = |node: &Node| node.store.lock().unwrap().get(&key).unwrap().expires;
+        assert!(expires(&remote1) <= expires(&local));
+    }
+
+    #[tokio::test]
+    async fn republish_own_record() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7210),
+            config(),
+        );
+        let mut remote = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7211),
+            config(),
+        );
+        local.table.lock().unwrap().add(Peer {
+            id: remote.id,
+            socket_addr: remote.socket,
+        });
+
+        // Local published the record long enough ago that it's due, but before it expires.
+        let key: Identifier = U256::from(3).into();
+        let value = b"sample".to_vec();
+        let mut record = Record::new(key, value.clone(), local.id);
+        record.received_at -= REPUBLISH_INTERVAL;
+        record.expires -= REPUBLISH_INTERVAL;
+        let aged_expires = record.expires;
+        let _ = local.store.lock().unwrap().put(record);
+
+        let _ = local.start().await;
+        let _ = remote.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        local.republish().await.unwrap();
+
+        // Republishing restarts the record's expiration, both locally and on peers.
+        let record = |node: &Node| node.store.lock().unwrap().get(&key).unwrap();
+        assert_eq!(record(&remote).value, value);
+        assert!(record(&remote).expires > aged_expires + REPLICATE_INTERVAL);
+        assert!(record(&local).expires > aged_expires + REPLICATE_INTERVAL);
+        assert_eq!(record(&local).publisher, local.id);
+    }
+
+    #[tokio::test]
+    async fn keep_own_record() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7212),
+            config(),
+        );
+        let mut remote = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7213),
+            config(),
+        );
+        remote.table.lock().unwrap().add(Peer {
+            id: local.id,
+            socket_addr: local.socket,
+        });
+
+        // Local published the record.  Remote holds an older copy that's due for replication.
+        let key: Identifier = U256::from(3).into();
+        let value = b"sample".to_vec();
+        let record = Record::new(key, value.clone(), local.id);
+        let _ = local.store.lock().unwrap().put(record.clone());
+        let mut held = record.clone();
+        held.received_at -= REPLICATE_INTERVAL;
+        held.expires -= REPLICATE_INTERVAL;
+        let _ = remote.store.lock().unwrap().put(held);
+
+        let _ = local.start().await;
+        let _ = remote.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // Remote replicates its copy back to the publisher, which keeps its own record.
+        remote.republish().await.unwrap();
+        assert_eq!(local.store.lock().unwrap().get(&key), Some(record));
+        assert_eq!(
+            remote.store.lock().unwrap().get(&key).unwrap().publisher,
+            local.id
+        );
+    }
+
+    #[tokio::test]
+    async fn evict_unresponsive_peer() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6400),
+            config(),
+        );
+        let mut remote = Node::new(
+            U256::from(24).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6424),
+            config(),
+        );
+
+        // Fill local's bucket for ids 16..32 with peers that aren't running.
+        {
+            let mut local_table = local.table.lock().unwrap();
+            for i in 16..16 + K as u16 {
+                let peer = Peer {
+                    id: U256::from(i).into(),
+                    socket_addr: socket::SocketAddr {
+                        addr: SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6400 + i),
+                    },
+                };
+                local_table.add(peer);
+            }
+        }
+        remote.table.lock().unwrap().add(Peer {
+            id: local.id,
+            socket_addr: local.socket,
+        });
+
+        let _ = local.start().await;
+        let _ = remote.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // Local's oldest peer doesn't answer the liveness ping, so remote takes its place.
+        assert_eq!(remote.ping(local.id).await, Ok(true));
+        tokio::time::sleep(Duration::from_secs(4)).await;
+
+        let local_table = local.table.lock().unwrap();
+        assert!(local_table.get(&U256::from(16).into()).is_none());
+        assert!(local_table.get(&remote.id).is_some());
+    }
+
+    #[tokio::test]
+    async fn keep_responsive_peer() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6500),
+            config(),
+        );
+        let mut oldest = Node::new(
+            U256::from(16).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6516),
+            config(),
+        );
+        let mut remote = Node::new(
+            U256::from(24).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6524),
+            config(),
+        );
+
+        // Fill local's bucket for ids 16..32.  Only the oldest peer is running.
+        {
+            let mut local_table = local.table.lock().unwrap();
+            for i in 16..16 + K as u16 {
+                let peer = Peer {
+                    id: U256::from(i).into(),
+                    socket_addr: socket::SocketAddr {
+                        addr: SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6500 + i),
+                    },
+                };
+                local_table.add(peer);
+            }
+        }
+        remote.table.lock().unwrap().add(Peer {
+            id: local.id,
+            socket_addr: local.socket,
+        });
+
+        let _ = local.start().await;
+        let _ = oldest.start().await;
+        let _ = remote.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // Local's oldest peer answers the liveness ping, so remote is dropped.
+        assert_eq!(remote.ping(local.id).await, Ok(true));
+        tokio::time::sleep(Duration::from_secs(4)).await;
+
+        let local_table = local.table.lock().unwrap();
+        assert!(local_table.get(&oldest.id).is_some());
+        assert!(local_table.get(&remote.id).is_none());
+    }
+
+    #[tokio::test]
+    async fn request_timeout() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6600),
+            config().with_request_timeout(Duration::from_millis(500)),
+        );
+
+        // Remote is never started, so it can't respond.
+        let remote = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6601),
+            config(),
+        );
+        local.table.lock().unwrap().add(Peer {
+            id: remote.id,
+            socket_addr: remote.socket,
+        });
+
+        let _ = local.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        assert_eq!(local.ping(remote.id).await, Err(Error::Timeout));
+        assert!(!local.table.lock().unwrap().buckets[0].entries[0].responsive);
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6700),
+            config(),
+        );
+        let mut remote = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6701),
+            config(),
+        );
+        let remote_peer = Peer {
+            id: remote.id,
+            socket_addr: remote.socket,
+        };
+        local.table.lock().unwrap().add(remote_peer);
+
+        // Populate remote's table
+        {
+            let mut remote_table = remote.table.lock().unwrap();
+            for i in 2..6 {
+                remote_table.add(Peer {
+                    id: U256::from(i).into(),
+                    socket_addr: socket::SocketAddr {
+                        addr: SocketAddr::new(
+                            "127.0.0.1".parse::<IpAddr>().unwrap(),
+                            6700 + i as u16,
+                        ),
+                    },
+                });
+            }
+        }
+
+        let _ = local.start().await;
+        let _ = remote.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // Several requests to the same peer are outstanding at once.  Each must be resolved by its own response.
+        let rx_1 = local
+            .find_node_targeted(U256::from(4).into(), remote_peer)
+            .await
+            .unwrap();
+        let rx_2 = local
+            .find_node_targeted(U256::from(5).into(), remote_peer)
+            .await
+            .unwrap();
+        let ping = local.ping(remote.id).await;
+
+        assert_eq!(ping, Ok(true));
+        assert!(matches!(rx_1.await.unwrap(), Ok(Some(peers)) if !peers.is_empty()));
+        assert!(matches!(rx_2.await.unwrap(), Ok(Some(peers)) if !peers.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn bootstrap() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6900),
+            config(),
+        );
+        let mut seed = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6901),
+            config(),
+        );
+        let seed_peer = Peer {
+            id: seed.id,
+            socket_addr: seed.socket,
+        };
+
+        // Seed knows of the rest of the network, and the rest of the network knows of seed.
+        let mut remotes = Vec::new();
+        for i in 2..5 {
+            let mut remote = Node::new(
+                U256::from(i).into(),
+                SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6900 + i as u16),
+                config(),
+            );
+            remote.table.lock().unwrap().add(seed_peer);
+            seed.table.lock().unwrap().add(Peer {
+                id: remote.id,
+                socket_addr: remote.socket,
+            });
+            let _ = remote.start().await;
+            remotes.push(remote);
+        }
+
+        let _ = local.start().await;
+        let _ = seed.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        assert_eq!(local.bootstrap(vec![seed_peer]).await, Ok(4));
+
+        let table = local.table.lock().unwrap();
+        assert_eq!(table.get(&seed.id), Some(seed_peer));
+        for remote in remotes {
+            assert!(table.get(&remote.id).is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn bootstrap_without_seeds() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6910),
+            config().with_request_timeout(Duration::from_millis(500)),
+        );
+
+        // Seed is never started, so it can't respond.
+        let seed = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 6911),
+            config(),
+        );
+
+        let _ = local.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        assert_eq!(local.bootstrap(vec![]).await, Err(Error::NoPeers));
+        assert_eq!(
+            local
+                .bootstrap(vec![Peer {
+                    id: seed.id,
+                    socket_addr: seed.socket,
+                }])
+                .await,
+            Err(Error::NoPeers)
+        );
+    }
+
+    #[tokio::test]
+    async fn find_node_empty_table() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7204),
+            config(),
+        );
+        let mut remote = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7205),
+            config(),
+        );
+        local.table.lock().unwrap().add(Peer {
+            id: remote.id,
+            socket_addr: remote.socket,
+        });
+
+        let _ = local.start().await;
+        let _ = remote.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // The remote doesn't know of any peers yet.
+        let closest_nodes = local.find_node(U256::from(2).into()).await;
+        assert_eq!(closest_nodes, Ok(Some(vec![])));
+
+        // The requester was inserted into the remote's table.
+        let local_peer = Peer {
+            id: local.id,
+            socket_addr: local.socket,
+        };
+        assert_eq!(
+            remote.table.lock().unwrap().get(&local.id),
+            Some(local_peer)
+        );
+    }
+
+    #[tokio::test]
+    async fn observe_senders() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7206),
+            config(),
+        );
+        let mut remote = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7207),
+            config(),
+        );
+        {
+            let mut table = local.table.lock().unwrap();
+            table.add(Peer {
+                id: remote.id,
+                socket_addr: remote.socket,
+            });
+            table.mark_unresponsive(&remote.id);
+        }
+
+        let _ = local.start().await;
+        let _ = remote.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        assert_eq!(local.ping(remote.id).await, Ok(true));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // The responder is seen again, and the requester is inserted.
+        assert!(local.table.lock().unwrap().buckets[0].entries[0].responsive);
+        assert_eq!(
+            remote.table.lock().unwrap().get(&local.id),
+            Some(Peer {
+                id: local.id,
+                socket_addr: local.socket,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn find_node_split_response() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7208),
+            Config::default(),
+        );
+        let mut remote = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7209),
+            Config::default(),
+        );
+        local.table.lock().unwrap().add(Peer {
+            id: remote.id,
+            socket_addr: remote.socket,
+        });
+
+        // The remote's K closest peers don't fit within a single datagram.
+        let k = Config::default().k;
+        for i in 2..k as u64 + 2 {
+            remote.table.lock().unwrap().add(Peer {
+                id: U256::from(i).into(),
+                socket_addr: socket::SocketAddr {
+                    addr: SocketAddr::new("::1".parse::<IpAddr>().unwrap(), 7300 + i as u16),
+                },
+            });
+        }
+
+        let _ = local.start().await;
+        let _ = remote.start().await;
         tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let closest_nodes = local.find_node(U256::from(2).into()).await;
+        assert!(matches!(closest_nodes, Ok(Some(peers)) if peers.len() == k));
+    }
+
+    #[tokio::test]
+    async fn survive_malformed_datagram() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7202),
+            config(),
+        );
+        let mut remote = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7203),
+            config(),
+        );
+        local.table.lock().unwrap().add(Peer {
+            id: remote.id,
+            socket_addr: remote.socket,
+        });
+
+        let _ = local.start().await;
+        let _ = remote.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let sender = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender
+            .send_to(b"garbage", remote.socket.addr)
+            .await
+            .unwrap();
+
+        // The remote's service is still running.
+        assert_eq!(local.ping(remote.id).await, Ok(true));
+        assert_eq!(remote.stats().dropped_datagrams(), 1);
+    }
+
+    #[tokio::test]
+    async fn typed_errors() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7200),
+            config(),
+        );
+        let remote = Peer {
+            id: U256::from(1).into(),
+            socket_addr: socket::SocketAddr {
+                addr: SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7201),
+            },
+        };
+
+        // Nothing to look up through.
+        assert_eq!(local.node_lookup(remote.id).await, Err(Error::NoPeers));
+        assert_eq!(local.get_value(remote.id).await, Err(Error::NoPeers));
+
+        // The local copy is stored before the value is replicated.
+        assert_eq!(
+            local.store(remote.id, vec![0; MAX_VALUE_BYTES + 1]).await,
+            Err(Error::Store(StoreError::ValueTooLarge))
+        );
+
+        // Requests can't be sent before the node is started.
+        local.table.lock().unwrap().add(remote);
+        assert_eq!(local.ping(remote.id).await, Err(Error::ChannelClosed));
+
+        // The socket is already in use.
+        let mut twin = local.clone();
+        local.start().await.unwrap();
+        assert_eq!(
+            twin.start().await,
+            Err(Error::Bind(io::ErrorKind::AddrInUse))
+        );
+    }
+
+    #[tokio::test]
+    async fn corrupt_table_file() {
+        let table_file = std::env::temp_dir().join(format!("table-{}.rlp", rand::random::<u64>()));
+        std::fs::write(&table_file, b"garbage").unwrap();
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7214),
+            config(),
+        )
+        .with_table_file(&table_file);
+
+        let result = local.start().await;
+        let _ = std::fs::remove_file(&table_file);
+        assert!(matches!(result, Err(Error::Codec(_))));
+    }
+
+    #[tokio::test]
+    async fn refresh_buckets() {
+        let mut local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7000),
+            config(),
+        );
+        let mut remote1 = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7001),
+            config(),
+        );
+        let mut remote2 = Node::new(
+            U256::from(2).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7002),
+            config(),
+        );
+        let remote1_peer = Peer {
+            id: remote1.id,
+            socket_addr: remote1.socket,
+        };
+        let remote2_peer = Peer {
+            id: remote2.id,
+            socket_addr: remote2.socket,
+        };
+        local.table.lock().unwrap().add(remote1_peer);
+        remote1.table.lock().unwrap().add(remote2_peer);
+        remote2.table.lock().unwrap().add(remote1_peer);
+
+        let _ = local.start().await;
+        let _ = remote1.start().await;
+        let _ = remote2.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // Nothing is refreshed until buckets have been idle for REFRESH_INTERVAL.
+        assert_eq!(local.refresh_buckets().await, Ok(0));
+
+        let later = Instant::now() + REFRESH_INTERVAL;
+        assert_eq!(local.refresh_idle_buckets(later).await, MAX_BUCKETS);
+
+        // Refreshing looked up an id within every bucket.
+        let table = local.table.lock().unwrap();
+        assert!(table.idle_buckets(later).is_empty());
+        assert_eq!(table.get(&remote2.id), Some(remote2_peer));
+    }
+
+    #[tokio::test]
+    async fn warm_restart() {
+        let table_file = std::env::temp_dir().join(format!("table-{}.rlp", rand::random::<u64>()));
+        let local = Node::new(
+            U256::from(0).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7100),
+            config(),
+        )
+        .with_table_file(&table_file);
+        let mut remote1 = Node::new(
+            U256::from(1).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7101),
+            config(),
+        );
+        // Remote2 is never started, so it can't respond.
+        let remote2 = Node::new(
+            U256::from(2).into(),
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7102),
+            config(),
+        );
+        for remote in [&remote1, &remote2] {
+            local.table.lock().unwrap().add(Peer {
+                id: remote.id,
+                socket_addr: remote.socket,
+            });
+        }
+        local.save_table().unwrap();
+
+        // The restarted node loads its saved table and rejoins through the peers within it.
+        let mut restarted = Node::new(
+            local.id,
+            SocketAddr::new("127.0.0.1".parse::<IpAddr>().unwrap(), 7103),
+            config().with_request_timeout(Duration::from_millis(500)),
+        )
+        .with_table_file(&table_file);
+
+        let _ = remote1.start().await;
+        let _ = restarted.start().await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let _ = std::fs::remove_file(&table_file);
+
+        assert_eq!(restarted.rejoin().await, Ok(1));
+
+        let table = restarted.table.lock().unwrap();
+        assert!(table.buckets[0].entries[0].responsive);
+        assert!(!table.buckets[1].entries[0].responsive);
     }
 }
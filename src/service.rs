@@ -1,24 +1,79 @@
+use crate::config::Config;
+use crate::error::Error;
 use crate::helper::Identifier;
-use crate::kbucket::KbucketTable;
-use crate::message::{Message, MessageBody};
-use crate::node::{Peer, K};
+use crate::kbucket::{InsertResult, KbucketTable};
+use crate::message::{Message, MessageBody, RequestId, ValueResponse};
+use crate::node::Peer;
 use crate::socket;
+use crate::store::{Record, RecordStore, StoreError, EXPIRE_INTERVAL, RECORD_TTL};
 use alloy_rlp::Decodable;
-use std::collections::HashMap;
-use std::io::Result;
-use std::net;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use std::{mem, net};
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
 
-// TODO: Handle errors properly
+#[derive(Debug)]
+pub struct OutboundRequest {
+    pub msg: Message,
+    pub deadline: Instant,
+    // Parts of a FoundNode response received so far, indexed by their position within the response.
+    pub parts: Vec<Option<Vec<Peer>>>,
+}
+
+impl OutboundRequest {
+    // Whether a FoundNode part belongs with the parts received so far.
+    fn accepts_part(&self, total: u8, index: u8) -> bool {
+        index < total && (self.parts.is_empty() || self.parts.len() == total as usize)
+    }
+
+    // Adds a FoundNode part.  Returns the peers from every part, in order, once all have arrived.
+    fn add_part(&mut self, total: u8, index: u8, peers: Vec<Peer>) -> Option<Vec<Peer>> {
+        if self.parts.is_empty() {
+            self.parts = vec![None; total as usize];
+        }
+        self.parts[index as usize] = Some(peers);
+        if self.parts.iter().any(Option::is_none) {
+            return None;
+        }
+        Some(self.parts.drain(..).flatten().flatten().collect())
+    }
+}
+
+/// Counts of inbound packets the service discarded.  Shared between the service and its node.
+#[derive(Debug, Default)]
+pub struct Stats {
+    // Responses dropped for not matching an outstanding request.
+    rejected_responses: AtomicU64,
+    // Datagrams dropped for not decoding to a message.
+    dropped_datagrams: AtomicU64,
+}
+
+impl Stats {
+    pub fn rejected_responses(&self) -> u64 {
+        self.rejected_responses.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_datagrams(&self) -> u64 {
+        self.dropped_datagrams.load(Ordering::Relaxed)
+    }
+}
 
 pub struct Service {
     pub local_record: Peer,
     pub socket: Arc<UdpSocket>,
     node_rx: mpsc::Receiver<Message>,
-    pub outbound_requests: HashMap<Identifier, Message>,
+    // Requests awaiting a response, keyed by the request id carried on the wire.
+    pub outbound_requests: HashMap<RequestId, OutboundRequest>,
+    pub config: Config,
     pub table: Arc<Mutex<KbucketTable>>,
+    pub store: Arc<Mutex<dyn RecordStore>>,
+    // Least-recently seen peers being pinged to check whether they should be evicted.
+    pending_evictions: HashSet<Identifier>,
+    pub stats: Arc<Stats>,
 }
 
 impl Service {
@@ -27,44 +82,70 @@ impl Service {
     pub async fn spawn(
         local_record: Peer,
         table: Arc<Mutex<KbucketTable>>,
-    ) -> Option<mpsc::Sender<Message>> {
-        let (service_tx, node_rx) = mpsc::channel(32);
+        store: Arc<Mutex<dyn RecordStore>>,
+        stats: Arc<Stats>,
+        config: Config,
+    ) -> Result<mpsc::Sender<Message>, Error> {
+        let (service_tx, node_rx) = mpsc::channel(config.channel_size);
+        let socket = UdpSocket::bind(net::SocketAddr::new(
+            local_record.socket_addr.addr.ip(),
+            local_record.socket_addr.addr.port(),
+        ))
+        .await
+        .map_err(|err| Error::Bind(err.kind()))?;
 
         let mut service = Service {
             local_record,
-            socket: Arc::new(
-                UdpSocket::bind(net::SocketAddr::new(
-                    local_record.socket_addr.addr.ip(),
-                    local_record.socket_addr.addr.port(),
-                ))
-                .await
-                .unwrap(),
-            ),
+            socket: Arc::new(socket),
             node_rx,
             outbound_requests: Default::default(),
+            config,
             table,
+            store,
+            pending_evictions: Default::default(),
+            stats,
         };
 
         tokio::spawn(async move {
             service.start().await;
         });
 
-        Some(service_tx)
+        Ok(service_tx)
     }
 
     // Node's main message processing loop
     pub async fn start(&mut self) {
+        let mut expire_interval = tokio::time::interval(EXPIRE_INTERVAL);
+        let mut timeout_interval = tokio::time::interval(Duration::from_millis(100));
+
+        // One byte larger than the maximum datagram size, so that oversized datagrams can be told apart.
+        let mut datagram = vec![0_u8; self.config.datagram_size + 1];
         loop {
-            let mut datagram = [0_u8; 1024];
             tokio::select! {
+                // Record Expiration:
+                _ = expire_interval.tick() => {
+                    self.store.lock().unwrap().remove_expired(SystemTime::now());
+                }
+
+                // Request Timeouts:
+                _ = timeout_interval.tick() => {
+                    self.expire_requests();
+                }
+
                 // Service Requests:
                 Some(service_msg) = self.node_rx.recv() => {
                     match service_msg.body {
                         MessageBody::Ping(_, _) => {
-                            let _ = self.send_message(service_msg).await;
+                            self.send_message(service_msg).await;
                         }
                         MessageBody::FindNode(_, _, _) => {
-                            let _ = self.send_message(service_msg).await;
+                            self.send_message(service_msg).await;
+                        }
+                        MessageBody::Store(..) => {
+                            self.send_message(service_msg).await;
+                        }
+                        MessageBody::FindValue(_, _, _) => {
+                            self.send_message(service_msg).await;
                         }
                         _ => {
                             println!("Service msg wasn't a request message");
@@ -73,34 +154,77 @@ impl Service {
                 }
 
                 // External Message Processing:
-                Ok((_, socket_addr)) = self.socket.recv_from(&mut datagram) => {
-                    let inbound_req = Message::decode(&mut datagram.to_vec().as_slice()).unwrap();
+                Ok((len, socket_addr)) = self.socket.recv_from(&mut datagram) => {
+                    let Some(inbound_req) = self.decode_datagram(&datagram[..len]) else {
+                        continue;
+                    };
                     let socket_addr = socket::SocketAddr { addr: socket_addr };
+                    let sender = Peer {id: inbound_req.body.sender(), socket_addr};
+                    let is_valid = inbound_req.body.is_request() || self.is_expected_response(sender.id, &inbound_req);
 
                     match &inbound_req.body {
                         MessageBody::Ping(id, None) => {
                             let target = Peer {id: *id, socket_addr};
-                            self.table.lock().unwrap().add(target);
-                            self.pong(inbound_req.session, target).await;
+                            self.pong(inbound_req.request_id, target).await;
                         }
                         MessageBody::Pong(id) => {
                             let target = Peer {id: *id, socket_addr};
-                            self.process_response(target.id, inbound_req);
+                            self.process_response(target.id, inbound_req).await;
                         }
                         MessageBody::FindNode(id, node_to_find, _) => {
                             let target = Peer {id: *id, socket_addr};
-                            let closest_nodes = self.table.lock().unwrap().get_closest_nodes(node_to_find, K).unwrap();
+                            let closest_nodes = self.table.lock().unwrap().get_closest_nodes(node_to_find, self.config.k).unwrap_or_default();
+                            self.found_node(inbound_req.request_id, target, closest_nodes).await;
+                        }
+                        MessageBody::FoundNode(id, _, _, _) => {
+                            let target = Peer {id: *id, socket_addr};
+                            self.process_response(target.id, inbound_req).await;
+                        }
+                        MessageBody::Store(id, key, value, ttl, publisher, _) => {
+                            let target = Peer {id: *id, socket_addr};
+                            let ttl = self.record_ttl(key, Duration::from_secs(*ttl));
+                            let record = Record::new(*key, value.clone(), *publisher).with_ttl(ttl);
+                            let stored = self.put_record(record);
 
-                            self.found_node(inbound_req.session, target, closest_nodes).await;
+                            // Only acknowledge records we've accepted.
+                            if stored.is_ok() {
+                                self.stored(inbound_req.request_id, target).await;
+                            }
                         }
-                        MessageBody::FoundNode(id, _, _) => {
+                        MessageBody::Stored(id) => {
                             let target = Peer {id: *id, socket_addr};
-                            self.process_response(target.id, inbound_req);
+                            self.process_response(target.id, inbound_req).await;
                         }
+                        MessageBody::FindValue(id, key, _) => {
+                            let target = Peer {id: *id, socket_addr};
+                            let record = self
+                                .store
+                                .lock()
+                                .unwrap()
+                                .get(key)
+                                .filter(|record| !record.is_expired(SystemTime::now()));
 
-                        _ => {
-                            unimplemented!()
+                            // Peers who don't hold the value respond as if it were a FindNode request.
+                            if let Some(record) = record {
+                                self.found_value(inbound_req.request_id, target, record).await;
+                            } else {
+                                let closest_nodes = self.table.lock().unwrap().get_closest_nodes(key, self.config.k).unwrap_or_default();
+                                self.found_node(inbound_req.request_id, target, closest_nodes).await;
+                            }
+                        }
+                        MessageBody::FoundValue(id, _, _) => {
+                            let target = Peer {id: *id, socket_addr};
+                            self.process_response(target.id, inbound_req).await;
                         }
+
+                        // Requests never carry a response channel on the wire.
+                        _ => {}
+                    }
+
+                    // Any valid message updates its sender within the routing table.  This is done after
+                    // handling the message, so that a FindNode requester isn't sent back to itself.
+                    if is_valid {
+                        self.add_peer(sender).await;
                     }
                 }
             }
@@ -109,71 +233,333 @@ impl Service {
 
     // Response Messages
     // ---------------------------------------------------------------------------------------------------
-    async fn pong(&mut self, session: u8, target: Peer) {
+    async fn pong(&mut self, request_id: RequestId, target: Peer) {
         let msg = Message {
             target,
-            session,
+            request_id,
             body: (MessageBody::Pong(self.local_record.id)),
         };
-        let _ = self.send_message(msg).await;
+        self.send_message(msg).await;
+    }
+
+    async fn found_node(&mut self, request_id: RequestId, target: Peer, closest_nodes: Vec<Peer>) {
+        let parts = match self.split_nodes(request_id, target, closest_nodes) {
+            Ok(parts) => parts,
+            Err(err) => {
+                println!("FoundNode response wasn't sent: {err}");
+                return;
+            }
+        };
+        let total = parts.len() as u8;
+        for (index, part) in parts.into_iter().enumerate() {
+            let msg = Message {
+                target,
+                request_id,
+                body: (MessageBody::FoundNode(self.local_record.id, total, index as u8, part)),
+            };
+            self.send_message(msg).await;
+        }
     }
 
-    async fn found_node(&mut self, session: u8, target: Peer, closest_nodes: Vec<Peer>) {
+    async fn stored(&mut self, request_id: RequestId, target: Peer) {
         let msg = Message {
             target,
-            session,
-            body: (MessageBody::FoundNode(
-                self.local_record.id,
-                closest_nodes.len() as u8,
-                closest_nodes,
-            )),
+            request_id,
+            body: (MessageBody::Stored(self.local_record.id)),
         };
-        let _ = self.send_message(msg).await;
+        self.send_message(msg).await;
+    }
+
+    // A value that doesn't fit within a datagram is answered as if it weren't held, so that the requester
+    // moves on to other peers rather than timing out.
+    async fn found_value(&mut self, request_id: RequestId, target: Peer, record: Record) {
+        let msg = Message {
+            target,
+            request_id,
+            body: (MessageBody::FoundValue(self.local_record.id, record.value, record.publisher)),
+        };
+        if socket::encoded(&msg).len() > self.config.datagram_size {
+            println!(
+                "FoundValue response doesn't fit within a datagram, sending closest peers instead"
+            );
+            let closest_nodes = self
+                .table
+                .lock()
+                .unwrap()
+                .get_closest_nodes(&record.key, self.config.k)
+                .unwrap_or_default();
+            self.found_node(request_id, target, closest_nodes).await;
+            return;
+        }
+        self.send_message(msg).await;
     }
 
     // Helper Functions
     // ---------------------------------------------------------------------------------------------------
-    async fn send_message(&mut self, msg: Message) -> Result<()> {
+    // Sends a message to its target.  Requests that can't be sent are failed straight away.  Returns whether
+    // the message was sent.
+    async fn send_message(&mut self, mut msg: Message) -> bool {
+        // Each request is assigned an id not used by any other outstanding request.
+        if msg.body.is_request() {
+            msg.request_id = self.unused_request_id(msg.request_id);
+        }
+
         let dest = net::SocketAddr::new(
             msg.target.socket_addr.addr.ip(),
             msg.target.socket_addr.addr.port(),
         );
 
         let message_bytes = socket::encoded(&msg);
-        let _ = self.socket.send_to(&message_bytes, dest).await.unwrap();
-        self.outbound_requests.insert(msg.target.id, msg);
-        Ok(())
+        if message_bytes.len() > self.config.datagram_size {
+            msg.body.fail(Error::MessageTooLarge);
+            return false;
+        }
+        if let Err(err) = self.socket.send_to(&message_bytes, dest).await {
+            msg.body.fail(Error::Io(err.kind()));
+            return false;
+        }
+
+        // Only requests await a response.
+        if msg.body.is_request() {
+            let deadline = Instant::now() + self.config.request_timeout;
+            self.outbound_requests.insert(
+                msg.request_id,
+                OutboundRequest {
+                    msg,
+                    deadline,
+                    parts: Vec::new(),
+                },
+            );
+        }
+        true
+    }
+
+    // Datagrams from peers are untrusted.  Those larger than the maximum datagram size or that don't
+    // decode to a message are dropped and counted.
+    fn decode_datagram(&mut self, datagram: &[u8]) -> Option<Message> {
+        let msg = (datagram.len() <= self.config.datagram_size)
+            .then(|| Message::decode(&mut &datagram[..]).ok())
+            .flatten();
+        if msg.is_none() {
+            self.stats.dropped_datagrams.fetch_add(1, Ordering::Relaxed);
+        }
+        msg
+    }
+
+    // Splits a FoundNode response's peers into parts that each fit within the maximum datagram size.  An
+    // empty list is still sent as a single part.  Parts are numbered with a u8, so peers beyond the first
+    // u8::MAX parts are left out.  Fails if a part holding a single peer doesn't fit.
+    fn split_nodes(
+        &self,
+        request_id: RequestId,
+        target: Peer,
+        nodes: Vec<Peer>,
+    ) -> Result<Vec<Vec<Peer>>, Error> {
+        let fits = |part: &[Peer]| {
+            let msg = Message {
+                target,
+                request_id,
+                body: MessageBody::FoundNode(self.local_record.id, u8::MAX, u8::MAX, part.to_vec()),
+            };
+            socket::encoded(&msg).len() <= self.config.datagram_size
+        };
+
+        if !fits(&[]) {
+            return Err(Error::MessageTooLarge);
+        }
+        let mut parts = vec![Vec::new()];
+        for node in nodes {
+            let mut part = parts.pop().unwrap_or_default();
+            part.push(node);
+            if part.len() > 1 && !fits(&part) {
+                part.pop();
+                parts.push(part);
+                if parts.len() == u8::MAX as usize {
+                    break;
+                }
+                part = vec![node];
+            }
+            if part.len() == 1 && !fits(&part) {
+                return Err(Error::MessageTooLarge);
+            }
+            parts.push(part);
+        }
+        Ok(parts)
+    }
+
+    // Stores a record sent by a peer.  Records we published are only refreshed by ourselves, so an inbound
+    // store never overwrites them.  A stored copy's expiration is never cut short by a copy with a shorter
+    // ttl, such as one cached along a lookup path.
+    fn put_record(&mut self, mut record: Record) -> Result<(), StoreError> {
+        let local_id = self.local_record.id;
+        let mut store = self.store.lock().unwrap();
+        if let Some(existing) = store.get(&record.key) {
+            if existing.publisher == local_id {
+                return Ok(());
+            }
+            record.expires = record.expires.max(existing.expires);
+        }
+        store.put(record)
+    }
+
+    // Keeps the proposed id unless it's already in use.
+    fn unused_request_id(&self, proposed: RequestId) -> RequestId {
+        let mut request_id = proposed;
+        while self.outbound_requests.contains_key(&request_id) {
+            request_id = rand::random();
+        }
+        request_id
+    }
+
+    // Records are kept for less time the more peers we know of that are closer to the key.
+    // This prevents over-caching of records far from the local node (Kademlia paper, 2.5).
+    fn record_ttl(&self, key: &Identifier, ttl: Duration) -> Duration {
+        let closer_peers = self.table.lock().unwrap().closer_peers(key);
+        let halvings = closer_peers.saturating_sub(self.config.k).min(31) as u32;
+
+        ttl.min(RECORD_TTL) / 2_u32.pow(halvings)
+    }
+
+    // Adds a peer to the routing table.  If the peer's bucket is full, the peer is cached as a replacement
+    // and the bucket's least-recently seen peer is pinged.  If the ping times out the peer is evicted and
+    // the most recent replacement is promoted.
+    async fn add_peer(&mut self, peer: Peer) {
+        let result = self.table.lock().unwrap().add(peer);
+
+        if let InsertResult::Full(oldest) = result {
+            if !self.pending_evictions.insert(oldest.id) {
+                return;
+            }
+
+            let msg = Message {
+                target: oldest,
+                request_id: rand::random(),
+                body: (MessageBody::Ping(self.local_record.id, None)),
+            };
+            // The oldest peer can be pinged again the next time its bucket is full.
+            if !self.send_message(msg).await {
+                self.pending_evictions.remove(&oldest.id);
+            }
+        }
+    }
+
+    // Whether a message answers one of our outstanding requests.
+    fn is_expected_response(&self, id: Identifier, inbound_resp: &Message) -> bool {
+        self.outbound_requests
+            .get(&inbound_resp.request_id)
+            .is_some_and(|request| {
+                let is_part = match inbound_resp.body {
+                    MessageBody::FoundNode(_, total, index, _) => {
+                        request.accepts_part(total, index)
+                    }
+                    _ => true,
+                };
+                request.msg.target.id == id
+                    && inbound_resp.body.is_response_to(&request.msg.body)
+                    && is_part
+            })
     }
 
-    // TODO: Remove id from parameter
+    // Fails requests that weren't answered in time and records the failure within the routing table.
+    fn expire_requests(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<RequestId> = self
+            .outbound_requests
+            .iter()
+            .filter(|(_, request)| request.deadline <= now)
+            .map(|(request_id, _)| *request_id)
+            .collect();
+
+        for request_id in expired {
+            if let Some(request) = self.outbound_requests.remove(&request_id) {
+                let id = request.msg.target.id;
+                let mut table = self.table.lock().unwrap();
+                if self.pending_evictions.remove(&id) {
+                    table.evict(&id);
+                } else {
+                    table.mark_unresponsive(&id);
+                }
+                request.msg.body.fail(Error::Timeout);
+            }
+        }
+    }
+
+    // Verifies msg received is legit wrt msg originally sent.
     //
-    // Verifies msg received is legit wrt msg originally sent
-    fn process_response(&mut self, id: Identifier, inbound_resp: Message) {
-        // Warning: This removes all outbound reqs to an individual node.
-        let local_msg = self.outbound_requests.remove(&id).unwrap();
+    // A response must echo the id of an outstanding request and come from the peer the request was sent
+    // to.  Otherwise it's rejected and counted.  A response of the wrong type for its request, or a
+    // FoundNode part that doesn't belong with those received so far, is also counted and fails the request
+    // as a protocol violation.
+    async fn process_response(&mut self, id: Identifier, mut inbound_resp: Message) {
+        let is_from_target = self
+            .outbound_requests
+            .get(&inbound_resp.request_id)
+            .is_some_and(|request| request.msg.target.id == id);
+        if !is_from_target {
+            self.stats
+                .rejected_responses
+                .fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let is_valid = self.is_expected_response(id, &inbound_resp);
+
+        // FoundNode responses split across datagrams stay pending until every part has arrived.
+        if let MessageBody::FoundNode(_, total, index, peers) = &mut inbound_resp.body {
+            let request = self.outbound_requests.get_mut(&inbound_resp.request_id);
+            if let (true, Some(request)) = (is_valid, request) {
+                match request.add_part(*total, *index, mem::take(peers)) {
+                    Some(closest_peers) => *peers = closest_peers,
+                    None => return,
+                }
+            }
+        }
+
+        let Some(OutboundRequest { msg: local_msg, .. }) =
+            self.outbound_requests.remove(&inbound_resp.request_id)
+        else {
+            return;
+        };
+        if !is_valid {
+            self.stats
+                .rejected_responses
+                .fetch_add(1, Ordering::Relaxed);
+            local_msg.body.fail(Error::ProtocolViolation);
+            return;
+        }
+
         match inbound_resp.body {
             MessageBody::Pong(_) => {
-                if let MessageBody::Ping(_, tx) = local_msg.body {
-                    if local_msg.session == inbound_resp.session {
-                        let _ = tx.unwrap().send(true);
-                    } else {
-                        let _ = tx.unwrap().send(false);
-                    }
+                // Peers that respond to an eviction ping stay in their bucket.
+                self.pending_evictions.remove(&id);
+
+                // Eviction pings sent by the service don't have a response channel.
+                if let MessageBody::Ping(_, Some(tx)) = local_msg.body {
+                    let _ = tx.send(Ok(true));
                 }
             }
-            MessageBody::FoundNode(_, _, closest_peers) => {
-                if let MessageBody::FindNode(_, _, tx) = local_msg.body {
-                    if local_msg.session == inbound_resp.session {
-                        let mut table = self.table.lock().unwrap();
-
-                        for peer in closest_peers.clone() {
-                            table.add(peer);
-                        }
+            MessageBody::FoundNode(_, _, _, closest_peers) => {
+                for peer in closest_peers.clone() {
+                    self.add_peer(peer).await;
+                }
 
-                        let _ = tx.unwrap().send(Some(closest_peers));
-                    } else {
-                        let _ = tx.unwrap().send(None);
+                match local_msg.body {
+                    MessageBody::FindNode(_, _, Some(tx)) => {
+                        let _ = tx.send(Ok(Some(closest_peers)));
+                    }
+                    MessageBody::FindValue(_, _, Some(tx)) => {
+                        let _ = tx.send(Ok(Some(ValueResponse::Peers(closest_peers))));
                     }
+                    _ => {}
+                }
+            }
+            MessageBody::FoundValue(_, value, publisher) => {
+                if let MessageBody::FindValue(_, _, Some(tx)) = local_msg.body {
+                    let _ = tx.send(Ok(Some(ValueResponse::Value(value, publisher))));
+                }
+            }
+            MessageBody::Stored(_) => {
+                if let MessageBody::Store(_, _, _, _, _, Some(tx)) = local_msg.body {
+                    let _ = tx.send(Ok(true));
                 }
             }
             _ => println!("Not a response message type."),
@@ -183,7 +569,40 @@ impl Service {
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use crate::config::DATAGRAM_SIZE;
+    use crate::helper::U256;
+    use crate::store::{MemoryStore, CACHE_TTL, MAX_VALUE_BYTES};
+    use tokio::sync::oneshot;
+
+    fn peer(i: u64, port: u16) -> Peer {
+        Peer {
+            id: U256::from(i).into(),
+            socket_addr: socket::SocketAddr {
+                addr: net::SocketAddr::new("127.0.0.1".parse::<net::IpAddr>().unwrap(), port),
+            },
+        }
+    }
+
+    async fn service(local_record: Peer) -> Service {
+        let config = Config::default();
+        let (_, node_rx) = mpsc::channel(config.channel_size);
+        Service {
+            local_record,
+            socket: Arc::new(
+                UdpSocket::bind(local_record.socket_addr.addr)
+                    .await
+                    .unwrap(),
+            ),
+            node_rx,
+            outbound_requests: Default::default(),
+            config,
+            table: Arc::new(Mutex::new(KbucketTable::new(local_record.id))),
+            store: Arc::new(Mutex::new(MemoryStore::default())),
+            pending_evictions: Default::default(),
+            stats: Default::default(),
+        }
+    }
 
     // Print statements currently show that table was updated from within the service!
     // TODO: Prove this within a test!
@@ -195,4 +614,277 @@ mod tests {
 
         // 3. Verify node's table is updated correctly.
     }
+
+    #[tokio::test]
+    async fn reject_unexpected_response() {
+        let local = peer(0, 6800);
+        let mut service = service(local).await;
+        let remote = peer(1, 6801);
+        let imposter = peer(2, 6802);
+
+        let (tx, mut rx) = oneshot::channel();
+        let request = Message {
+            target: remote,
+            request_id: rand::random(),
+            body: MessageBody::Ping(local.id, Some(tx)),
+        };
+        service.send_message(request).await;
+        let request_id = *service.outbound_requests.keys().next().unwrap();
+
+        // Unknown request id.
+        let mut unknown_id = request_id;
+        unknown_id[0] ^= 1;
+        let pong = |request_id| Message {
+            target: local,
+            request_id,
+            body: MessageBody::Pong(remote.id),
+        };
+        service.process_response(remote.id, pong(unknown_id)).await;
+
+        // Known request id, but sent by a peer the request wasn't sent to.
+        service
+            .process_response(imposter.id, pong(request_id))
+            .await;
+
+        assert_eq!(service.stats.rejected_responses(), 2);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(service.outbound_requests.len(), 1);
+
+        service.process_response(remote.id, pong(request_id)).await;
+        assert_eq!(service.stats.rejected_responses(), 2);
+        assert_eq!(rx.await.unwrap(), Ok(true));
+        assert!(service.outbound_requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn fail_wrong_response_type() {
+        let local = peer(0, 6803);
+        let mut service = service(local).await;
+        let remote = peer(1, 6804);
+
+        let (tx, rx) = oneshot::channel();
+        let request = Message {
+            target: remote,
+            request_id: rand::random(),
+            body: MessageBody::Ping(local.id, Some(tx)),
+        };
+        service.send_message(request).await;
+        let request_id = *service.outbound_requests.keys().next().unwrap();
+
+        // Known request id from the right peer, but the wrong response type.
+        let stored = Message {
+            target: local,
+            request_id,
+            body: MessageBody::Stored(remote.id),
+        };
+        service.process_response(remote.id, stored).await;
+
+        assert_eq!(service.stats.rejected_responses(), 1);
+        assert_eq!(rx.await.unwrap(), Err(Error::ProtocolViolation));
+        assert!(service.outbound_requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn drop_malformed_datagram() {
+        let local = peer(0, 6805);
+        let mut service = service(local).await;
+        let remote = peer(1, 6806);
+
+        assert!(service.decode_datagram(b"garbage").is_none());
+        assert!(service.decode_datagram(&[]).is_none());
+
+        let ping = Message {
+            target: local,
+            request_id: rand::random(),
+            body: MessageBody::Ping(remote.id, None),
+        };
+        let datagram = socket::encoded(&ping);
+        assert!(service.decode_datagram(&datagram).is_some());
+        assert!(service
+            .decode_datagram(&datagram[..datagram.len() - 1])
+            .is_none());
+        assert_eq!(service.stats.dropped_datagrams(), 3);
+
+        // Datagrams larger than the maximum size are dropped, even if they'd decode.
+        service.config.datagram_size = datagram.len() - 1;
+        assert!(service.decode_datagram(&datagram).is_none());
+        assert_eq!(service.stats.dropped_datagrams(), 4);
+    }
+
+    #[tokio::test]
+    async fn split_found_node() {
+        let local = peer(0, 6807);
+        let service = service(local).await;
+        let remote = peer(1, 6808);
+
+        // K IPv6 peers don't fit within a single datagram.
+        let nodes: Vec<Peer> = (2..22)
+            .map(|i| Peer {
+                id: U256::from(i).into(),
+                socket_addr: socket::SocketAddr {
+                    addr: net::SocketAddr::new(
+                        "::1".parse::<net::IpAddr>().unwrap(),
+                        7000 + i as u16,
+                    ),
+                },
+            })
+            .collect();
+        let parts = service
+            .split_nodes(rand::random(), remote, nodes.clone())
+            .unwrap();
+        assert!(parts.len() > 1);
+
+        let total = parts.len() as u8;
+        for (index, part) in parts.iter().enumerate() {
+            let msg = Message {
+                target: remote,
+                request_id: rand::random(),
+                body: MessageBody::FoundNode(local.id, total, index as u8, part.clone()),
+            };
+            assert!(socket::encoded(&msg).len() <= service.config.datagram_size);
+        }
+        assert_eq!(parts.concat(), nodes);
+
+        // An empty list is still sent.
+        assert_eq!(
+            service.split_nodes(rand::random(), remote, vec![]),
+            Ok(vec![vec![]])
+        );
+    }
+
+    #[tokio::test]
+    async fn split_found_node_limits() {
+        let local = peer(0, 6814);
+        let mut service = service(local).await;
+        let remote = peer(1, 6815);
+        let request_id = rand::random();
+        let empty = Message {
+            target: remote,
+            request_id,
+            body: MessageBody::FoundNode(local.id, u8::MAX, u8::MAX, vec![]),
+        };
+        let empty_len = socket::encoded(&empty).len();
+        let nodes: Vec<Peer> = (2..302).map(|i| peer(i, 7000)).collect();
+
+        // Each part only fits a single peer, and parts are numbered with a u8.
+        service.config.datagram_size = empty_len + 60;
+        let parts = service
+            .split_nodes(request_id, remote, nodes.clone())
+            .unwrap();
+        assert_eq!(parts.len(), u8::MAX as usize);
+        assert_eq!(parts.concat(), nodes[..u8::MAX as usize]);
+
+        // A single peer doesn't fit.
+        service.config.datagram_size = empty_len + 10;
+        assert_eq!(
+            service.split_nodes(request_id, remote, nodes),
+            Err(Error::MessageTooLarge)
+        );
+    }
+
+    #[tokio::test]
+    async fn unsent_eviction_ping() {
+        let local = peer(0, 6816);
+        let mut service = service(local).await;
+
+        // The bucket's peers can't be reached from an IPv4 socket, so pings to them fail to send.
+        let unreachable = |i: u64| Peer {
+            id: U256::from(i).into(),
+            socket_addr: socket::SocketAddr {
+                addr: net::SocketAddr::new("::1".parse::<net::IpAddr>().unwrap(), 6817),
+            },
+        };
+        for i in 32..32 + service.config.k as u64 {
+            service.table.lock().unwrap().add(unreachable(i));
+        }
+        let oldest = unreachable(32);
+
+        service.add_peer(peer(63, 6818)).await;
+        assert!(!service.pending_evictions.contains(&oldest.id));
+        assert!(service.outbound_requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn keep_later_expiration() {
+        let local = peer(0, 6819);
+        let mut service = service(local).await;
+        let key = U256::from(1).into();
+        let publisher = U256::from(2).into();
+
+        let stored = Record::new(key, b"stored".to_vec(), publisher);
+        service.put_record(stored.clone()).unwrap();
+        let cached = Record::new(key, b"cached".to_vec(), publisher).with_ttl(CACHE_TTL);
+        service.put_record(cached).unwrap();
+
+        let record = service.store.lock().unwrap().get(&key).unwrap();
+        assert_eq!(record.value, b"cached");
+        assert_eq!(record.expires, stored.expires);
+    }
+
+    #[tokio::test]
+    async fn oversized_found_value() {
+        let local = peer(0, 6820);
+        let mut service = service(local).await;
+        let remote = peer(1, 6821);
+        let remote_socket = UdpSocket::bind(remote.socket_addr.addr).await.unwrap();
+        let record = Record::new(U256::from(2).into(), vec![0; MAX_VALUE_BYTES], remote.id);
+
+        // The value doesn't fit, so the requester is sent peers instead.
+        service.config.datagram_size = MAX_VALUE_BYTES;
+        let request_id = rand::random();
+        service.found_value(request_id, remote, record).await;
+
+        let mut datagram = [0_u8; DATAGRAM_SIZE];
+        let len = remote_socket.recv(&mut datagram).await.unwrap();
+        let response = Message::decode(&mut &datagram[..len]).unwrap();
+        assert_eq!(response.request_id, request_id);
+        assert!(matches!(response.body, MessageBody::FoundNode(..)));
+    }
+
+    #[tokio::test]
+    async fn collect_found_node_parts() {
+        let local = peer(0, 6809);
+        let mut service = service(local).await;
+        let remote = peer(1, 6810);
+        let nodes = [peer(2, 6811), peer(3, 6812), peer(4, 6813)];
+
+        let find_node = |tx| Message {
+            target: remote,
+            request_id: rand::random(),
+            body: MessageBody::FindNode(local.id, remote.id, Some(tx)),
+        };
+        let found_node = |request_id, total, index, part: &[Peer]| Message {
+            target: local,
+            request_id,
+            body: MessageBody::FoundNode(remote.id, total, index, part.to_vec()),
+        };
+
+        let (tx, mut rx) = oneshot::channel();
+        service.send_message(find_node(tx)).await;
+        let request_id = *service.outbound_requests.keys().next().unwrap();
+
+        // Parts can arrive out of order.
+        service
+            .process_response(remote.id, found_node(request_id, 2, 1, &nodes[2..]))
+            .await;
+        assert!(rx.try_recv().is_err());
+        service
+            .process_response(remote.id, found_node(request_id, 2, 0, &nodes[..2]))
+            .await;
+        assert_eq!(rx.await.unwrap(), Ok(Some(nodes.to_vec())));
+        assert!(service.outbound_requests.is_empty());
+
+        // A part that doesn't belong with those received so far.
+        let (tx, rx) = oneshot::channel();
+        service.send_message(find_node(tx)).await;
+        let request_id = *service.outbound_requests.keys().next().unwrap();
+        service
+            .process_response(remote.id, found_node(request_id, 2, 0, &nodes[..2]))
+            .await;
+        service
+            .process_response(remote.id, found_node(request_id, 3, 2, &nodes[2..]))
+            .await;
+        assert_eq!(rx.await.unwrap(), Err(Error::ProtocolViolation));
+        assert_eq!(service.stats.rejected_responses(), 1);
+    }
 }
@@ -59,7 +59,7 @@ impl Decodable for SocketAddr {
                 let port = u16::decode(&mut payload)?;
                 net::SocketAddr::new(ip.into(), port)
             }
-            _ => panic!("Not a SocketAddr"),
+            _ => return Err(Error::Custom("unknown address type")),
         };
 
         Ok(Self { addr })
@@ -70,6 +70,7 @@ impl Decodable for SocketAddr {
 mod test {
     use super::*;
     use alloy_rlp::Decodable;
+    use proptest::prelude::*;
     use std::net::IpAddr;
 
     #[test]
@@ -97,4 +98,33 @@ mod test {
         let recovered = Vec::<SocketAddr>::decode(&mut out.as_slice()).unwrap();
         assert_eq!(foos, recovered);
     }
+
+    #[test]
+    fn reject_unknown_address_type() {
+        let mut enc: [&dyn Encodable; 3] = [b""; 3];
+        enc[0] = &2_u8;
+        enc[1] = &[1_u8; 4];
+        enc[2] = &69_u16;
+        let mut out = BytesMut::new();
+        encode_list::<_, dyn Encodable>(&enc, &mut out);
+
+        let result = SocketAddr::decode(&mut out.to_vec().as_slice());
+        assert_eq!(result, Err(Error::Custom("unknown address type")));
+    }
+
+    proptest! {
+        #[test]
+        fn decode_arbitrary_bytes(data in proptest::collection::vec(any::<u8>(), 0..64)) {
+            let _ = SocketAddr::decode(&mut data.as_slice());
+        }
+
+        #[test]
+        fn decode_encoded(ip in any::<IpAddr>(), port in any::<u16>()) {
+            let socket_addr = SocketAddr {
+                addr: net::SocketAddr::new(ip, port),
+            };
+            let out = encoded(&socket_addr);
+            prop_assert_eq!(SocketAddr::decode(&mut out.to_vec().as_slice()), Ok(socket_addr));
+        }
+    }
 }
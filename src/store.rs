@@ -0,0 +1,533 @@
+use crate::helper::Identifier;
+use alloy_rlp::{encode_list, Decodable, Encodable, Error};
+use bytes::{Buf, BufMut, Bytes};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const MAX_RECORDS: usize = 1024;
+pub const MAX_VALUE_BYTES: usize = 512; // Values must fit within a single datagram
+pub const RECORD_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+pub const CACHE_TTL: Duration = Duration::from_secs(60 * 60); // Copies cached along a lookup path
+pub const REPUBLISH_INTERVAL: Duration = Duration::from_secs(22 * 60 * 60); // Ahead of RECORD_TTL
+pub const REPLICATE_INTERVAL: Duration = Duration::from_secs(60 * 60); // Record holders
+pub const EXPIRE_INTERVAL: Duration = Duration::from_secs(60);
+
+const LOG_FILE: &str = "records.log";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub key: Identifier,
+    pub value: Vec<u8>,
+    pub publisher: Identifier,
+    pub expires: SystemTime,
+    pub received_at: SystemTime,
+}
+
+impl Record {
+    pub fn new(key: Identifier, value: Vec<u8>, publisher: Identifier) -> Self {
+        let now = SystemTime::now();
+        Self {
+            key,
+            value,
+            publisher,
+            expires: now + RECORD_TTL,
+            received_at: now,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.expires = self.received_at + ttl;
+        self
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires <= now
+    }
+}
+
+//  Timestamps are stored as whole seconds since the unix epoch.
+//  +-----+-------+-----------+---------+-------------+
+//  | key | value | publisher | expires | received_at |
+//  +-----+-------+-----------+---------+-------------+
+impl Encodable for Record {
+    fn encode(&self, out: &mut dyn BufMut) {
+        let value: &[u8] = &self.value;
+        let expires = unix_secs(self.expires);
+        let received_at = unix_secs(self.received_at);
+
+        let mut enc: [&dyn Encodable; 5] = [b""; 5];
+        enc[0] = &self.key;
+        enc[1] = &value;
+        enc[2] = &self.publisher;
+        enc[3] = &expires;
+        enc[4] = &received_at;
+        encode_list::<_, dyn Encodable>(&enc, out);
+    }
+}
+
+impl Decodable for Record {
+    fn decode(data: &mut &[u8]) -> Result<Self, Error> {
+        let mut payload = alloy_rlp::Header::decode_bytes(data, true)?;
+
+        let key = Identifier::decode(&mut payload)?;
+        let value = Bytes::decode(&mut payload)?;
+        let publisher = Identifier::decode(&mut payload)?;
+        let expires = u64::decode(&mut payload)?;
+        let received_at = u64::decode(&mut payload)?;
+
+        let out_of_range = Error::Custom("timestamp out of range");
+        Ok(Self {
+            key,
+            value: value.to_vec(),
+            publisher,
+            expires: from_unix_secs(expires).ok_or(out_of_range)?,
+            received_at: from_unix_secs(received_at).ok_or(out_of_range)?,
+        })
+    }
+}
+
+// Fails for timestamps too far in the future to represent.
+pub(crate) fn from_unix_secs(secs: u64) -> Option<SystemTime> {
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+pub(crate) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StoreError {
+    MaxRecords,
+    ValueTooLarge,
+    Corrupt,
+    Io(io::ErrorKind),
+}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        StoreError::Io(err.kind())
+    }
+}
+
+/// Local storage for records accepted through the STORE rpc.
+///
+/// The service owns a store next to its routing table.  Implement this trait to
+/// swap in your own storage backend.
+pub trait RecordStore: Debug + Send {
+    fn get(&self, key: &Identifier) -> Option<Record>;
+    fn put(&mut self, record: Record) -> Result<(), StoreError>;
+    fn remove(&mut self, key: &Identifier) -> Option<Record>;
+    fn iter(&self) -> Box<dyn Iterator<Item = Record> + '_>;
+
+    /// Restores previously persisted records.  Called when the node starts.
+    fn load(&mut self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Removes every record that expired before `now`.  Returns the number removed.
+    fn remove_expired(&mut self, now: SystemTime) -> usize {
+        let expired: Vec<Identifier> = self
+            .iter()
+            .filter(|record| record.is_expired(now))
+            .map(|record| record.key)
+            .collect();
+
+        for key in &expired {
+            self.remove(key);
+        }
+        expired.len()
+    }
+}
+
+/// Default in-memory record store.  Records are dropped when the process exits.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryStore {
+    records: HashMap<Identifier, Record>,
+    max_records: usize,
+    max_value_bytes: usize,
+}
+
+impl MemoryStore {
+    pub fn new(max_records: usize, max_value_bytes: usize) -> Self {
+        Self {
+            records: HashMap::new(),
+            max_records,
+            max_value_bytes,
+        }
+    }
+
+    // Checks that a record can be stored without storing it.
+    fn check(&self, record: &Record) -> Result<(), StoreError> {
+        if record.value.len() > self.max_value_bytes {
+            return Err(StoreError::ValueTooLarge);
+        }
+        // Overwriting an existing record doesn't count towards the limit.
+        if self.records.len() >= self.max_records && !self.records.contains_key(&record.key) {
+            return Err(StoreError::MaxRecords);
+        }
+        Ok(())
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new(MAX_RECORDS, MAX_VALUE_BYTES)
+    }
+}
+
+impl RecordStore for MemoryStore {
+    fn get(&self, key: &Identifier) -> Option<Record> {
+        self.records.get(key).cloned()
+    }
+
+    fn put(&mut self, record: Record) -> Result<(), StoreError> {
+        self.check(&record)?;
+        self.records.insert(record.key, record);
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &Identifier) -> Option<Record> {
+        self.records.remove(key)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Record> + '_> {
+        Box::new(self.records.values().cloned())
+    }
+}
+
+// Entries within the append log.
+enum LogEntry {
+    Put(Record),        // 0
+    Remove(Identifier), // 1
+}
+
+impl Encodable for LogEntry {
+    fn encode(&self, out: &mut dyn BufMut) {
+        match self {
+            Self::Put(record) => {
+                let mut enc: [&dyn Encodable; 2] = [b""; 2];
+                enc[0] = &0_u8;
+                enc[1] = record;
+                encode_list::<_, dyn Encodable>(&enc, out);
+            }
+            Self::Remove(key) => {
+                let mut enc: [&dyn Encodable; 2] = [b""; 2];
+                enc[0] = &1_u8;
+                enc[1] = key;
+                encode_list::<_, dyn Encodable>(&enc, out);
+            }
+        }
+    }
+}
+
+impl Decodable for LogEntry {
+    fn decode(data: &mut &[u8]) -> Result<Self, Error> {
+        let mut payload = alloy_rlp::Header::decode_bytes(data, true)?;
+
+        let typ = u8::decode(&mut payload)?;
+        let entry = match typ {
+            0 => LogEntry::Put(Record::decode(&mut payload)?),
+            1 => LogEntry::Remove(Identifier::decode(&mut payload)?),
+            _ => return Err(Error::Custom("Unknown log entry")),
+        };
+        Ok(entry)
+    }
+}
+
+// Whether the log ends partway through the entry at the start of buf.
+fn is_truncated(mut buf: &[u8]) -> bool {
+    match alloy_rlp::Header::decode(&mut buf) {
+        Ok(header) => header.payload_length > buf.len(),
+        Err(err) => err == Error::InputTooShort,
+    }
+}
+
+/// Record store persisted to an append log within `dir`.
+///
+/// Records are served from memory.  Every put and remove is appended to the log, which is
+/// replayed and compacted by `load()` when the node starts.
+#[derive(Debug)]
+pub struct DiskStore {
+    dir: PathBuf,
+    records: MemoryStore,
+    log: Option<File>,
+}
+
+impl DiskStore {
+    pub fn new(dir: impl Into<PathBuf>, max_records: usize, max_value_bytes: usize) -> Self {
+        Self {
+            dir: dir.into(),
+            records: MemoryStore::new(max_records, max_value_bytes),
+            log: None,
+        }
+    }
+
+    fn log_path(&self) -> PathBuf {
+        self.dir.join(LOG_FILE)
+    }
+
+    fn append(&mut self, entry: LogEntry) -> Result<(), StoreError> {
+        if self.log.is_none() {
+            fs::create_dir_all(&self.dir)?;
+            let log = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.log_path())?;
+            self.log = Some(log);
+        }
+
+        let mut out = Vec::new();
+        entry.encode(&mut out);
+        if let Some(log) = self.log.as_mut() {
+            log.write_all(&out)?;
+            log.sync_data()?;
+        }
+        Ok(())
+    }
+
+    // Rewrites the log so that it only holds live records.
+    fn compact(&mut self) -> Result<(), StoreError> {
+        let mut out = Vec::new();
+        for record in self.records.iter() {
+            LogEntry::Put(record).encode(&mut out);
+        }
+
+        let tmp_path = self.dir.join(format!("{LOG_FILE}.tmp"));
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&out)?;
+        tmp.sync_all()?;
+        fs::rename(tmp_path, self.log_path())?;
+
+        self.log = None;
+        Ok(())
+    }
+}
+
+impl RecordStore for DiskStore {
+    fn get(&self, key: &Identifier) -> Option<Record> {
+        self.records.get(key)
+    }
+
+    // The entry is logged before the record is served, so a failed write doesn't leave a record in memory
+    // that won't survive a restart.
+    fn put(&mut self, record: Record) -> Result<(), StoreError> {
+        self.records.check(&record)?;
+        self.append(LogEntry::Put(record.clone()))?;
+        self.records.put(record)
+    }
+
+    fn remove(&mut self, key: &Identifier) -> Option<Record> {
+        let record = self.records.remove(key)?;
+        let _ = self.append(LogEntry::Remove(*key));
+        Some(record)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Record> + '_> {
+        self.records.iter()
+    }
+
+    fn load(&mut self) -> Result<(), StoreError> {
+        fs::create_dir_all(&self.dir)?;
+        let data = match fs::read(self.log_path()) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        // A partially written final entry (e.g. after a crash) ends the replay.  Any other entry that can't be
+        // decoded fails the load, and the log is left as is rather than compacted without the entries after it.
+        let mut buf = data.as_slice();
+        while buf.has_remaining() {
+            let mut entry = buf;
+            match LogEntry::decode(&mut entry) {
+                Ok(LogEntry::Put(record)) => {
+                    let _ = self.records.put(record);
+                }
+                Ok(LogEntry::Remove(key)) => {
+                    self.records.remove(&key);
+                }
+                Err(_) if is_truncated(buf) => break,
+                Err(_) => return Err(StoreError::Corrupt),
+            }
+            buf = entry;
+        }
+
+        self.compact()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::helper::U256;
+    use rand::Rng;
+
+    #[test]
+    fn reject_out_of_range_timestamp() {
+        let key: Identifier = U256::from(1).into();
+        let value: &[u8] = b"sample";
+        let mut enc: [&dyn Encodable; 5] = [b""; 5];
+        enc[0] = &key;
+        enc[1] = &value;
+        enc[2] = &key;
+        enc[3] = &u64::MAX;
+        enc[4] = &0_u64;
+        let mut out = Vec::new();
+        encode_list::<_, dyn Encodable>(&enc, &mut out);
+
+        let result = Record::decode(&mut out.as_slice());
+        assert_eq!(result, Err(Error::Custom("timestamp out of range")));
+    }
+
+    #[test]
+    fn memory_store() {
+        let mut store = MemoryStore::default();
+        let record = Record::new(
+            U256::from(1).into(),
+            b"sample".to_vec(),
+            U256::from(0).into(),
+        );
+
+        assert_eq!(store.put(record.clone()), Ok(()));
+        assert_eq!(store.get(&record.key), Some(record.clone()));
+        assert_eq!(store.iter().count(), 1);
+        assert_eq!(store.remove(&record.key), Some(record.clone()));
+        assert_eq!(store.get(&record.key), None);
+    }
+
+    #[test]
+    fn memory_store_limits() {
+        let mut store = MemoryStore::new(2, 4);
+        for i in 0..2 {
+            let record = Record::new(U256::from(i).into(), vec![0; 4], U256::from(0).into());
+            assert_eq!(store.put(record), Ok(()));
+        }
+
+        let record = Record::new(U256::from(2).into(), vec![0; 4], U256::from(0).into());
+        assert_eq!(store.put(record), Err(StoreError::MaxRecords));
+
+        // Existing records can still be updated while the store is full.
+        let record = Record::new(U256::from(1).into(), vec![1; 4], U256::from(0).into());
+        assert_eq!(store.put(record), Ok(()));
+
+        let record = Record::new(U256::from(1).into(), vec![0; 5], U256::from(0).into());
+        assert_eq!(store.put(record), Err(StoreError::ValueTooLarge));
+    }
+
+    #[test]
+    fn remove_expired() {
+        let mut store = MemoryStore::default();
+        let now = SystemTime::now();
+        let fresh = Record::new(
+            U256::from(1).into(),
+            b"fresh".to_vec(),
+            U256::from(0).into(),
+        );
+        let stale = Record::new(
+            U256::from(2).into(),
+            b"stale".to_vec(),
+            U256::from(0).into(),
+        )
+        .with_ttl(Duration::from_secs(0));
+        let _ = store.put(fresh.clone());
+        let _ = store.put(stale.clone());
+
+        assert_eq!(store.remove_expired(now + Duration::from_secs(1)), 1);
+        assert_eq!(store.get(&fresh.key), Some(fresh));
+        assert_eq!(store.get(&stale.key), None);
+    }
+
+    #[test]
+    fn disk_store_reload() {
+        let dir = std::env::temp_dir().join(format!(
+            "my_kademlia_store_{}",
+            rand::thread_rng().gen::<u64>()
+        ));
+        let record1 = Record::new(
+            U256::from(1).into(),
+            b"sample".to_vec(),
+            U256::from(0).into(),
+        );
+        let record2 = Record::new(
+            U256::from(2).into(),
+            b"removed".to_vec(),
+            U256::from(0).into(),
+        );
+
+        {
+            let mut store = DiskStore::new(&dir, MAX_RECORDS, MAX_VALUE_BYTES);
+            assert_eq!(store.load(), Ok(()));
+            assert_eq!(store.put(record1.clone()), Ok(()));
+            assert_eq!(store.put(record2.clone()), Ok(()));
+            assert!(store.remove(&record2.key).is_some());
+        }
+
+        // Simulates a restart.  Timestamps only survive with second precision.
+        let mut store = DiskStore::new(&dir, MAX_RECORDS, MAX_VALUE_BYTES);
+        assert_eq!(store.load(), Ok(()));
+        let reloaded = store.get(&record1.key).unwrap();
+        assert_eq!(reloaded.value, record1.value);
+        assert_eq!(reloaded.publisher, record1.publisher);
+        assert_eq!(unix_secs(reloaded.expires), unix_secs(record1.expires));
+        assert_eq!(
+            unix_secs(reloaded.received_at),
+            unix_secs(record1.received_at)
+        );
+        assert_eq!(store.get(&record2.key), None);
+        assert_eq!(store.iter().count(), 1);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn disk_store_damaged_log() {
+        let dir = std::env::temp_dir().join(format!(
+            "my_kademlia_store_{}",
+            rand::thread_rng().gen::<u64>()
+        ));
+        let log_path = dir.join(LOG_FILE);
+        let records: Vec<Record> = (1..4)
+            .map(|i| {
+                Record::new(
+                    U256::from(i).into(),
+                    b"sample".to_vec(),
+                    U256::from(0).into(),
+                )
+            })
+            .collect();
+        let mut entries: Vec<Vec<u8>> = records
+            .iter()
+            .map(|record| {
+                let mut out = Vec::new();
+                LogEntry::Put(record.clone()).encode(&mut out);
+                out
+            })
+            .collect();
+        fs::create_dir_all(&dir).unwrap();
+
+        // A truncated final entry is dropped.
+        let mut data = entries[..2].concat();
+        data.extend_from_slice(&entries[2][..entries[2].len() / 2]);
+        fs::write(&log_path, &data).unwrap();
+        let mut store = DiskStore::new(&dir, MAX_RECORDS, MAX_VALUE_BYTES);
+        assert_eq!(store.load(), Ok(()));
+        assert_eq!(store.iter().count(), 2);
+        assert_eq!(store.get(&records[2].key), None);
+
+        // A corrupt entry within the log fails the load and leaves the log untouched.
+        entries[1][2] = u8::MAX;
+        let data = entries.concat();
+        fs::write(&log_path, &data).unwrap();
+        let mut store = DiskStore::new(&dir, MAX_RECORDS, MAX_VALUE_BYTES);
+        assert_eq!(store.load(), Err(StoreError::Corrupt));
+        assert_eq!(fs::read(&log_path).unwrap(), data);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}